@@ -69,7 +69,12 @@
               ];
               postFixup = ''
                 wrapProgram $out/bin/vpod \
-                  --set PATH ${lib.makeBinPath [ pkgsLatest.yt-dlp ]}
+                  --set PATH ${
+                    lib.makeBinPath [
+                      pkgs.ffmpeg-headless
+                      pkgsLatest.yt-dlp
+                    ]
+                  }
               '';
             };
           };
@@ -108,6 +113,7 @@
             packages =
               (old.packages or [ ])
               ++ (with pkgs; [
+                ffmpeg-headless
                 flyctl
                 just
                 pkgsLatest.yt-dlp
@@ -0,0 +1,248 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File, FileTimes},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use crate::cli::Cli;
+use crate::error::Result;
+use crate::feed::Store;
+
+use super::{AudioFormat, EpisodeMeta, STAGING_DIR};
+
+/// Leftovers from yt-dlp older than this are assumed to belong to a dead run.
+const STALE_AFTER: Duration = Duration::from_secs(60 * 60);
+
+/// Keeps the episodes downloaded for every feed within one byte budget.
+///
+/// Each subdirectory of `root` is a feed. Episodes are evicted least recently
+/// served first, across all feeds, except for the newest few of each feed.
+#[derive(Clone, Debug)]
+pub(crate) struct Cache {
+    root: PathBuf,
+    max_bytes: u64,
+    min_free_bytes: u64,
+    keep_newest: usize,
+    /// Where evicted episodes are forgotten.
+    store: Store,
+}
+
+#[derive(Debug)]
+struct CachedFile {
+    path: PathBuf,
+    size: u64,
+    accessed: SystemTime,
+    modified: SystemTime,
+}
+
+impl Cache {
+    pub(crate) fn new(cli: &Cli, store: Store) -> Self {
+        Self {
+            root: cli.data_dir.clone(),
+            max_bytes: cli.cache_max_bytes,
+            min_free_bytes: cli.cache_min_free_bytes,
+            keep_newest: cli.cache_keep_newest,
+            store,
+        }
+    }
+
+    /// Record that `path` was just served, so it is the last to be evicted.
+    ///
+    /// The access time is set explicitly since most filesystems are mounted
+    /// `noatime` or `relatime` and would not update it on read.
+    pub(crate) fn touch(&self, path: &Path) {
+        let touched = File::open(path)
+            .and_then(|file| file.set_times(FileTimes::new().set_accessed(SystemTime::now())));
+        if let Err(e) = touched {
+            tracing::warn!("could not record access to {}: {e}", path.display());
+        }
+    }
+
+    /// Periodically sweep the cache on a blocking thread.
+    pub(crate) fn spawn_sweeper(self, interval: Duration) {
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let cache = self.clone();
+                match tokio::task::spawn_blocking(move || cache.sweep()).await {
+                    Ok(Err(e)) => tracing::error!("cache sweep failed: {e:?}"),
+                    Err(e) => tracing::error!("cache sweep panicked: {e}"),
+                    Ok(Ok(())) => (),
+                }
+            }
+        });
+    }
+
+    /// Remove stale partial downloads, then evict episodes until the cache is
+    /// within budget and the disk has enough free space.
+    #[tracing::instrument(skip(self))]
+    pub(crate) fn sweep(&self) -> Result<()> {
+        let mut by_feed: HashMap<PathBuf, Vec<CachedFile>> = HashMap::new();
+
+        for feed_dir in fs::read_dir(&self.root)? {
+            let feed_dir = feed_dir?.path();
+            if !feed_dir.is_dir() || is_hidden(&feed_dir) {
+                continue;
+            }
+
+            remove_stale(&feed_dir.join(STAGING_DIR), |_| true);
+            remove_stale(&feed_dir, is_partial);
+
+            let files = fs::read_dir(&feed_dir)?
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| is_media(path))
+                .filter_map(|path| {
+                    let metadata = path.metadata().ok()?;
+                    Some(CachedFile {
+                        size: metadata.len(),
+                        accessed: metadata.accessed().ok()?,
+                        modified: metadata.modified().ok()?,
+                        path,
+                    })
+                })
+                .collect();
+            by_feed.insert(feed_dir, files);
+        }
+
+        let mut total: u64 = by_feed.values().flatten().map(|file| file.size).sum();
+        let mut free = fs2::available_space(&self.root)?;
+
+        let mut candidates: Vec<CachedFile> = by_feed
+            .into_values()
+            .flat_map(|mut files| {
+                files.sort_by_key(|file| std::cmp::Reverse(file.modified));
+                files.into_iter().skip(self.keep_newest)
+            })
+            .collect();
+        candidates.sort_by_key(|file| file.accessed);
+
+        let mut evicted = 0;
+        for file in candidates {
+            if total <= self.max_bytes && free >= self.min_free_bytes {
+                break;
+            }
+            match fs::remove_file(&file.path) {
+                Ok(()) => {
+                    tracing::debug!("evicted {}", file.path.display());
+                    EpisodeMeta::forget(&self.store, &file.path);
+                    total -= file.size;
+                    free += file.size;
+                    evicted += 1;
+                }
+                Err(e) => tracing::warn!("could not evict {}: {e}", file.path.display()),
+            }
+        }
+
+        if total > self.max_bytes || free < self.min_free_bytes {
+            tracing::warn!(
+                total,
+                free,
+                "cache is still over budget, only pinned episodes are left"
+            );
+        }
+        tracing::debug!(evicted, total, free, "swept cache");
+
+        Ok(())
+    }
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| name.starts_with('.'))
+}
+
+/// Finished episodes, which count towards the cache budget.
+fn is_media(path: &Path) -> bool {
+    AudioFormat::from_file_name(path).is_some()
+}
+
+/// Files yt-dlp and ffmpeg write while a download is still in progress.
+fn is_partial(path: &Path) -> bool {
+    let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+        return false;
+    };
+    name.ends_with(".part")
+        || name.ends_with(".ytdl")
+        || name.contains(".part-Frag")
+        || name.contains(".temp.")
+}
+
+fn remove_stale(dir: &Path, matches: impl Fn(&Path) -> bool) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    for path in entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+    {
+        let stale = path
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .is_some_and(|age| age > STALE_AFTER);
+
+        if path.is_file() && stale && matches(&path) {
+            match fs::remove_file(&path) {
+                Ok(()) => tracing::debug!("removed stale {}", path.display()),
+                Err(e) => tracing::warn!("could not remove stale {}: {e}", path.display()),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_episode(path: &Path, size: usize, modified_secs_ago: u64, accessed_secs_ago: u64) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, vec![0u8; size]).unwrap();
+        let now = SystemTime::now();
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_times(
+                FileTimes::new()
+                    .set_modified(now - Duration::from_secs(modified_secs_ago))
+                    .set_accessed(now - Duration::from_secs(accessed_secs_ago)),
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_sweep_evicts_least_recently_served_across_feeds() {
+        let root = std::env::temp_dir().join(format!("vpod-cache-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+
+        // Newest episode in each feed is pinned, whatever its access time.
+        write_episode(&root.join("feed-a/new.m4a"), 100, 10, 5000);
+        write_episode(&root.join("feed-a/old.m4a"), 100, 100, 10);
+        write_episode(&root.join("feed-b/new.m4a"), 100, 10, 5000);
+        write_episode(&root.join("feed-b/old.m4a"), 100, 100, 1000);
+        write_episode(&root.join("feed-b/stale.m4a.part"), 100, 2 * 60 * 60, 0);
+
+        let cache = Cache {
+            root: root.clone(),
+            max_bytes: 300,
+            min_free_bytes: 0,
+            keep_newest: 1,
+            store: Store::in_memory().unwrap(),
+        };
+        cache.sweep().unwrap();
+
+        assert!(root.join("feed-a/new.m4a").exists());
+        assert!(root.join("feed-a/old.m4a").exists());
+        assert!(root.join("feed-b/new.m4a").exists());
+        assert!(!root.join("feed-b/old.m4a").exists());
+        assert!(!root.join("feed-b/stale.m4a.part").exists());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,389 @@
+use std::{fs, path::Path, time::Duration};
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use serde::Serialize;
+use tower::ServiceExt;
+
+use super::{fetch_segments, staged, AudioFormat, EpisodeFile, EpisodeMeta};
+use crate::error::{Result, VpodError};
+use crate::feed::VideoDetails;
+use crate::sponsorblock::{self, Segment};
+use crate::state::AppState;
+
+/// What chapter file names end in, e.g. `abc.chapters.json` for `abc.m4a`.
+pub(crate) const SUFFIX: &str = ".chapters.json";
+
+/// How long built chapters are served before they are built again, so new
+/// SponsorBlock segments and edited descriptions show up eventually.
+const MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+const VERSION: &str = "1.2.0";
+
+/// A Podcasting 2.0 JSON chapters file.
+///
+/// See <https://github.com/Podcastindex-org/podcast-namespace/blob/main/chapters/jsonChapters.md>.
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct Chapters {
+    version: &'static str,
+    chapters: Vec<Chapter>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Chapter {
+    start_time: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    end_time: Option<f64>,
+    title: String,
+    /// `Some(false)` keeps a chapter out of the table of contents, for
+    /// SponsorBlock segments that overlap the real chapters.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    toc: Option<bool>,
+}
+
+/// Serve the chapters of the episode `stem` of `feed_id`, building them
+/// first if they aren't cached or have gone stale.
+///
+/// `stem` is the episode's file name without extension, so sped-up variants
+/// get chapters at their own speed.
+#[tracing::instrument(skip(state, request))]
+pub(crate) async fn serve_chapters(
+    state: &AppState,
+    feed_id: &str,
+    stem: &str,
+    request: axum::extract::Request,
+) -> Result<Response> {
+    // The path is only ever built from the parsed stem, which can't leave
+    // the feed's directory.
+    let Some(file) = EpisodeFile::parse_stem(stem, AudioFormat::default()) else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    let settings = state.config.feed(feed_id);
+    if file.stem() != stem
+        || file
+            .tempo
+            .is_some_and(|tempo| !settings.offers_tempo(tempo))
+        || !settings.keeps_video_timing()
+    {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+    let ep_id = file.ep_id.to_owned();
+    let tempo = file.tempo;
+
+    let path = state
+        .feed_dir(feed_id)
+        .join(format!("{}{SUFFIX}", file.stem()));
+    if is_stale(&path) {
+        let _ = fs::remove_file(&path);
+    }
+    let profile = state.config.profile(feed_id);
+    // Cut segments are taken from the file they were cut from.
+    let base = state.feed_dir(feed_id).join(
+        EpisodeFile {
+            tempo: None,
+            format: profile.codec,
+            ..file
+        }
+        .file_name(),
+    );
+
+    state
+        .downloads
+        .fetch(&path, || {
+            let pool = state.pool.clone();
+            let api = state.sponsorblock_api.clone();
+            let source = state.source.clone();
+            let store = state.store.clone();
+            let path = path.clone();
+            async move {
+                let details = source.video_details(&ep_id).await.map_err(|e| {
+                    tracing::error!("could not get video details: {e:?}");
+                    e.vpod_error().cloned().unwrap_or(VpodError::YoutubeDLError)
+                })?;
+                let (marked, removed) = if profile.sponsorblock.removes_segments() {
+                    let removed = store
+                        .blocking(move |store| {
+                            Ok(EpisodeMeta::load(store, &base).and_then(|meta| meta.removed_spans))
+                        })
+                        .await
+                        .map_err(|e| {
+                            tracing::error!("could not load episode metadata: {e:?}");
+                            VpodError::YoutubeDLError
+                        })?;
+                    (vec![], removed.ok_or(VpodError::ChaptersUnavailable)?)
+                } else {
+                    let marked = fetch_segments(&api, &ep_id, &profile.sponsorblock)
+                        .await
+                        .unwrap_or_default();
+                    (marked, vec![])
+                };
+                pool.run(move || {
+                    let chapters = Chapters::build(&details, &marked, &removed, tempo);
+                    write(&path, &chapters).map_err(|e| {
+                        tracing::error!("could not write chapters: {e:?}");
+                        VpodError::YoutubeDLError
+                    })
+                })
+                .await
+            }
+        })
+        .await?;
+
+    let service = tower_http::services::ServeFile::new(&path);
+    Ok(service.oneshot(request).await.into_response())
+}
+
+impl Chapters {
+    /// Chapters from the video's own chapter list, or failing that the
+    /// timestamps in its description, with the `marked` SponsorBlock
+    /// segments added and moved up past the `removed` spans of the video, as
+    /// returned by [`sponsorblock::merged`].
+    fn build(
+        details: &VideoDetails,
+        marked: &[Segment],
+        removed: &[(f64, f64)],
+        tempo: Option<f64>,
+    ) -> Self {
+        let mut chapters = match details.chapters.is_empty() {
+            false => details
+                .chapters
+                .iter()
+                .map(|chapter| Chapter {
+                    start_time: chapter.start_secs,
+                    end_time: chapter.end_secs,
+                    title: chapter.title.clone(),
+                    toc: None,
+                })
+                .collect(),
+            true => from_description(&details.description),
+        };
+
+        if !removed.is_empty() {
+            let cut = |time: f64| sponsorblock::cut_time(removed, time);
+            chapters = chapters
+                .into_iter()
+                .map(|chapter| Chapter {
+                    start_time: cut(chapter.start_time),
+                    end_time: chapter.end_time.map(cut),
+                    ..chapter
+                })
+                .filter(|chapter| chapter.end_time.is_none_or(|end| end > chapter.start_time))
+                .collect();
+        }
+        chapters.extend(marked.iter().map(|segment| Chapter {
+            start_time: segment.segment.0,
+            end_time: Some(segment.segment.1),
+            title: segment.category.title().to_string(),
+            toc: Some(false),
+        }));
+
+        chapters.sort_by(|a, b| a.start_time.total_cmp(&b.start_time));
+
+        if let Some(tempo) = tempo {
+            for chapter in &mut chapters {
+                chapter.start_time /= tempo;
+                chapter.end_time = chapter.end_time.map(|end| end / tempo);
+            }
+        }
+
+        Self {
+            version: VERSION,
+            chapters,
+        }
+    }
+}
+
+/// Chapters from timestamps in a description, e.g. `0:00 Intro`, following
+/// YouTube's own rules: at least three, ascending, the first at `0:00`.
+fn from_description(description: &str) -> Vec<Chapter> {
+    let chapters: Vec<Chapter> = description
+        .lines()
+        .filter_map(timestamped)
+        .map(|(start_time, title)| Chapter {
+            start_time,
+            end_time: None,
+            title,
+            toc: None,
+        })
+        .collect();
+
+    let valid = chapters.len() >= 3
+        && chapters[0].start_time == 0.0
+        && chapters
+            .windows(2)
+            .all(|pair| pair[0].start_time < pair[1].start_time);
+    if valid {
+        chapters
+    } else {
+        vec![]
+    }
+}
+
+/// The timestamp and title of a line like `0:00 Intro`, `(1:02:03) Q&A` or
+/// `Outro - 12:34`.
+fn timestamped(line: &str) -> Option<(f64, String)> {
+    let (stamp, secs) = line.split_whitespace().find_map(|word| {
+        let secs = parse_timestamp(word.trim_matches(|c| "()[]".contains(c)))?;
+        Some((word, secs))
+    })?;
+
+    let title = line
+        .replacen(stamp, "", 1)
+        .trim_matches(|c: char| c.is_whitespace() || "-–—:|•".contains(c))
+        .to_string();
+    if title.is_empty() {
+        return None;
+    }
+
+    Some((secs, title))
+}
+
+/// Seconds in `m:ss` or `h:mm:ss`.
+fn parse_timestamp(stamp: &str) -> Option<f64> {
+    let parts: Vec<u32> = stamp
+        .split(':')
+        .map(|part| match part.chars().all(|c| c.is_ascii_digit()) {
+            true => part.parse().ok(),
+            false => None,
+        })
+        .collect::<Option<_>>()?;
+
+    let secs = match parts[..] {
+        [minutes, seconds] if seconds < 60 => minutes * 60 + seconds,
+        [hours, minutes, seconds] if minutes < 60 && seconds < 60 => {
+            hours * 60 * 60 + minutes * 60 + seconds
+        }
+        _ => return None,
+    };
+    Some(f64::from(secs))
+}
+
+fn write(path: &Path, chapters: &Chapters) -> Result<()> {
+    let staged = staged(path)?;
+    fs::write(&staged, serde_json::to_vec(chapters)?)?;
+    fs::rename(&staged, path)?;
+    Ok(())
+}
+
+/// Remove the chapters of `ep_id` in `feed_dir`, at any speed, so they are
+/// built again for what the file is now.
+pub(crate) fn remove(feed_dir: &Path, ep_id: &str) {
+    let Ok(entries) = fs::read_dir(feed_dir) else {
+        return;
+    };
+
+    for path in entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+    {
+        let is_chapters = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix(SUFFIX))
+            .and_then(|stem| EpisodeFile::parse_stem(stem, AudioFormat::default()))
+            .is_some_and(|file| file.ep_id == ep_id);
+        if is_chapters {
+            tracing::debug!("removing stale chapters {}", path.display());
+            let _ = fs::remove_file(&path);
+        }
+    }
+}
+
+fn is_stale(path: &Path) -> bool {
+    path.metadata()
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_some_and(|age| age > MAX_AGE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::SponsorCategory;
+    use crate::feed::VideoChapter;
+
+    fn sponsor(start: f64, end: f64) -> Segment {
+        Segment {
+            segment: (start, end),
+            uuid: format!("{start}-{end}"),
+            action_type: "skip".to_string(),
+            category: SponsorCategory::Sponsor,
+        }
+    }
+
+    fn starts(chapters: &Chapters) -> Vec<(f64, &str)> {
+        chapters
+            .chapters
+            .iter()
+            .map(|chapter| (chapter.start_time, chapter.title.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn test_chapters_from_description() {
+        let details = VideoDetails {
+            description: "Thanks for watching!\n\
+                          0:00 Intro\n\
+                          (2:30) - The problem\n\
+                          Q&A | 1:02:03\n\
+                          Follow me at 10:00pm"
+                .to_string(),
+            ..Default::default()
+        };
+
+        let chapters = Chapters::build(&details, &[], &[], None);
+
+        assert_eq!(
+            starts(&chapters),
+            [(0.0, "Intro"), (150.0, "The problem"), (3723.0, "Q&A")]
+        );
+    }
+
+    #[test]
+    fn test_stray_timestamps_are_not_chapters() {
+        let details = VideoDetails {
+            description: "Live at 1:00\nRecorded 0:00 to 2:00".to_string(),
+            ..Default::default()
+        };
+
+        let chapters = Chapters::build(&details, &[], &[], None);
+
+        assert!(chapters.chapters.is_empty());
+    }
+
+    #[test]
+    fn test_segments_are_marked_or_cut() {
+        let details = VideoDetails {
+            chapters: vec![
+                VideoChapter {
+                    start_secs: 0.0,
+                    end_secs: Some(100.0),
+                    title: "Intro".to_string(),
+                },
+                VideoChapter {
+                    start_secs: 100.0,
+                    end_secs: Some(400.0),
+                    title: "Talk".to_string(),
+                },
+            ],
+            ..Default::default()
+        };
+        let segments = [sponsor(40.0, 100.0)];
+
+        let marked = Chapters::build(&details, &segments, &[], None);
+        assert_eq!(
+            starts(&marked),
+            [(0.0, "Intro"), (40.0, "Sponsor"), (100.0, "Talk")]
+        );
+        assert_eq!(marked.chapters[1].toc, Some(false));
+
+        let removed = sponsorblock::merged(&segments);
+        let cut = Chapters::build(&details, &[], &removed, Some(2.0));
+        assert_eq!(starts(&cut), [(0.0, "Intro"), (20.0, "Talk")]);
+        assert_eq!(cut.chapters[1].end_time, Some(170.0));
+    }
+}
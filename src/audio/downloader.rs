@@ -0,0 +1,289 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{Cursor, Read},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    thread,
+};
+
+use ytd_rs::Arg;
+
+use super::{AudioFormat, DownloadProfile};
+
+/// Which [`Downloader`] the server runs with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub(crate) enum DownloaderKind {
+    YtDlp,
+    Fake,
+}
+
+impl DownloaderKind {
+    pub(crate) fn build(self) -> Arc<dyn Downloader> {
+        match self {
+            Self::YtDlp => Arc::new(YtDlp),
+            Self::Fake => Arc::new(FakeDownloader::default()),
+        }
+    }
+}
+
+/// Why a downloader run failed.
+#[derive(Debug)]
+pub(crate) enum RunError {
+    /// The downloader ran and failed, printing this to stderr.
+    Failed(String),
+    /// The downloader could not be run at all.
+    Unavailable(String),
+}
+
+/// An episode being downloaded to a stream instead of a file.
+pub(crate) struct Streamed {
+    pub(crate) output: Box<dyn Read + Send>,
+    /// Waits for the download to end, once `output` has been read to the end.
+    pub(crate) finish: Box<dyn FnOnce() -> Result<(), RunError> + Send>,
+}
+
+/// Fetches episodes and what is known about them from YouTube.
+///
+/// Runs on the download pool's threads, so every method may block.
+pub(crate) trait Downloader: Send + Sync {
+    /// Download `ep_id` as `format` into `dir`, as `{ep_id}.{ext}`.
+    fn download(
+        &self,
+        ep_id: &str,
+        dir: &Path,
+        profile: &DownloadProfile,
+        format: AudioFormat,
+    ) -> Result<(), RunError>;
+
+    /// Start downloading `ep_id` as AAC without converting it, so it can be
+    /// handed on while it arrives.
+    fn stream(&self, ep_id: &str, profile: &DownloadProfile) -> Result<Streamed, RunError>;
+
+    /// What yt-dlp's `--dump-json` prints for `ep_id`.
+    fn video_info(&self, ep_id: &str, dir: &Path) -> Result<String, RunError>;
+
+    /// Write `language` captions for `ep_id` into `dir`, as
+    /// `{ep_id}.{language}.vtt`. Writes nothing if the video has none.
+    fn subtitles(&self, ep_id: &str, dir: &Path, language: &str) -> Result<(), RunError>;
+}
+
+/// The real thing, running yt-dlp.
+pub(crate) struct YtDlp;
+
+impl YtDlp {
+    fn run(dir: &Path, args: Vec<Arg>, ep_id: &str) -> Result<String, RunError> {
+        let url = watch_url(ep_id);
+        let ytd = ytd_rs::YoutubeDL::new(&dir.to_path_buf(), args, &url)
+            .map_err(|e| RunError::Unavailable(e.to_string()))?;
+
+        match ytd.download() {
+            Ok(result) => Ok(result.output().to_owned()),
+            Err(ytd_rs::error::YoutubeDLError::Failure(stderr)) => Err(RunError::Failed(stderr)),
+            Err(e) => Err(RunError::Unavailable(e.to_string())),
+        }
+    }
+}
+
+impl Downloader for YtDlp {
+    fn download(
+        &self,
+        ep_id: &str,
+        dir: &Path,
+        profile: &DownloadProfile,
+        format: AudioFormat,
+    ) -> Result<(), RunError> {
+        let mut args = profile.args(format);
+        args.push(Arg::new_with_arg("--output", "%(id)s.%(ext)s"));
+        Self::run(dir, args, ep_id).map(drop)
+    }
+
+    fn stream(&self, ep_id: &str, profile: &DownloadProfile) -> Result<Streamed, RunError> {
+        let mut child = Command::new("yt-dlp")
+            .args(["--quiet", "--no-part"])
+            .args([
+                "--concurrent-fragments",
+                &profile.concurrent_fragments.to_string(),
+            ])
+            .args(["--format", &profile.format_with_ext("m4a")])
+            .args(["--output", "-"])
+            .arg(watch_url(ep_id))
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| RunError::Unavailable(e.to_string()))?;
+
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or(RunError::Unavailable("yt-dlp has no stdout".to_string()))?;
+        let mut stderr = child
+            .stderr
+            .take()
+            .ok_or(RunError::Unavailable("yt-dlp has no stderr".to_string()))?;
+        // Read on the side, so yt-dlp never blocks on a full stderr pipe.
+        let stderr = thread::spawn(move || {
+            let mut text = String::new();
+            let _ = stderr.read_to_string(&mut text);
+            text
+        });
+
+        Ok(Streamed {
+            output: Box::new(stdout),
+            finish: Box::new(move || {
+                let status = child
+                    .wait()
+                    .map_err(|e| RunError::Unavailable(e.to_string()))?;
+                let stderr = stderr.join().unwrap_or_default();
+                match status.success() {
+                    true => Ok(()),
+                    false => Err(RunError::Failed(stderr)),
+                }
+            }),
+        })
+    }
+
+    fn video_info(&self, ep_id: &str, dir: &Path) -> Result<String, RunError> {
+        let args = vec![
+            Arg::new("--quiet"),
+            Arg::new("--no-playlist"),
+            Arg::new("--dump-json"),
+        ];
+        Self::run(dir, args, ep_id)
+    }
+
+    fn subtitles(&self, ep_id: &str, dir: &Path, language: &str) -> Result<(), RunError> {
+        let args = vec![
+            Arg::new("--quiet"),
+            Arg::new("--no-playlist"),
+            Arg::new("--skip-download"),
+            Arg::new("--write-subs"),
+            Arg::new("--write-auto-subs"),
+            Arg::new_with_arg("--sub-langs", language),
+            Arg::new_with_arg("--sub-format", "vtt"),
+            Arg::new_with_arg("--convert-subs", "vtt"),
+            Arg::new_with_arg("--output", "%(id)s.%(ext)s"),
+        ];
+        Self::run(dir, args, ep_id).map(drop)
+    }
+}
+
+fn watch_url(ep_id: &str) -> String {
+    format!("https://www.youtube.com/watch?v={ep_id}")
+}
+
+/// Stands in for yt-dlp without touching the network.
+///
+/// Every episode is the same fixture audio, with chapters in its description
+/// and English captions only. Videos can be made to fail with what yt-dlp
+/// would print for them.
+#[derive(Debug, Default)]
+pub(crate) struct FakeDownloader {
+    failures: HashMap<String, String>,
+    downloads: AtomicUsize,
+    subtitle_runs: AtomicUsize,
+}
+
+/// Size of the fixture every fake episode is made of.
+pub(crate) const FAKE_EPISODE_BYTES: usize = 64 * 1024;
+
+const FAKE_VIDEO_INFO: &str = r#"{
+    "id": "fake",
+    "title": "Fake episode",
+    "duration": 180,
+    "description": "Fixture for offline tests.\n0:00 Intro\n1:00 Middle\n2:00 End"
+}"#;
+
+const FAKE_CAPTIONS: &str = "WEBVTT\n\n\
+    00:00:00.000 --> 00:00:02.500\n\
+    hello from the fixture\n";
+
+impl FakeDownloader {
+    /// Fail every run for `ep_id`, with `stderr` as yt-dlp's output.
+    #[cfg(test)]
+    pub(crate) fn failing(mut self, ep_id: &str, stderr: &str) -> Self {
+        self.failures.insert(ep_id.to_string(), stderr.to_string());
+        self
+    }
+
+    /// How many episodes have been downloaded, streamed or not.
+    #[cfg(test)]
+    pub(crate) fn downloads(&self) -> usize {
+        self.downloads.load(Ordering::SeqCst)
+    }
+
+    /// How many times captions have been asked for, in any language.
+    #[cfg(test)]
+    pub(crate) fn subtitle_runs(&self) -> usize {
+        self.subtitle_runs.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn episode(ep_id: &str) -> Vec<u8> {
+        ep_id
+            .bytes()
+            .chain(std::iter::repeat(0))
+            .take(FAKE_EPISODE_BYTES)
+            .collect()
+    }
+
+    fn check(&self, ep_id: &str) -> Result<(), RunError> {
+        match self.failures.get(ep_id) {
+            Some(stderr) => Err(RunError::Failed(stderr.clone())),
+            None => Ok(()),
+        }
+    }
+
+    fn write(path: PathBuf, contents: &[u8]) -> Result<(), RunError> {
+        fs::create_dir_all(path.parent().unwrap_or(Path::new(".")))
+            .and_then(|()| fs::write(path, contents))
+            .map_err(|e| RunError::Unavailable(e.to_string()))
+    }
+}
+
+impl Downloader for FakeDownloader {
+    fn download(
+        &self,
+        ep_id: &str,
+        dir: &Path,
+        _profile: &DownloadProfile,
+        format: AudioFormat,
+    ) -> Result<(), RunError> {
+        self.check(ep_id)?;
+        self.downloads.fetch_add(1, Ordering::SeqCst);
+        Self::write(
+            dir.join(format!("{ep_id}.{}", format.extension())),
+            &Self::episode(ep_id),
+        )
+    }
+
+    fn stream(&self, ep_id: &str, _profile: &DownloadProfile) -> Result<Streamed, RunError> {
+        self.check(ep_id)?;
+        self.downloads.fetch_add(1, Ordering::SeqCst);
+        Ok(Streamed {
+            output: Box::new(Cursor::new(Self::episode(ep_id))),
+            finish: Box::new(|| Ok(())),
+        })
+    }
+
+    fn video_info(&self, ep_id: &str, _dir: &Path) -> Result<String, RunError> {
+        self.check(ep_id)?;
+        Ok(FAKE_VIDEO_INFO.to_string())
+    }
+
+    fn subtitles(&self, ep_id: &str, dir: &Path, language: &str) -> Result<(), RunError> {
+        self.check(ep_id)?;
+        self.subtitle_runs.fetch_add(1, Ordering::SeqCst);
+        // Like yt-dlp, succeed without writing anything.
+        if language != "en" {
+            return Ok(());
+        }
+        Self::write(
+            dir.join(format!("{ep_id}.{language}.vtt")),
+            FAKE_CAPTIONS.as_bytes(),
+        )
+    }
+}
@@ -0,0 +1,134 @@
+use crate::error::VpodError;
+
+/// What a failed yt-dlp run means for the request that triggered it.
+#[derive(Debug)]
+pub(crate) enum Failure {
+    /// The video can't be fetched as things stand; retrying won't help.
+    Permanent(VpodError),
+    /// Most likely a network or upstream hiccup; worth another attempt.
+    Transient,
+}
+
+// Matched case-insensitively against yt-dlp's stderr, first match wins.
+const PERMANENT: &[(&str, VpodError)] = &[
+    ("private video", VpodError::VideoPrivate),
+    ("this video is private", VpodError::VideoPrivate),
+    ("members-only", VpodError::MembersOnly),
+    (
+        "available to this channel's members",
+        VpodError::MembersOnly,
+    ),
+    ("join this channel to get access", VpodError::MembersOnly),
+    ("confirm your age", VpodError::AgeRestricted),
+    ("age-restricted", VpodError::AgeRestricted),
+    ("inappropriate for some users", VpodError::AgeRestricted),
+    ("not available in your country", VpodError::GeoBlocked),
+    (
+        "not made this video available in your country",
+        VpodError::GeoBlocked,
+    ),
+    ("geo restriction", VpodError::GeoBlocked),
+    ("geo-restricted", VpodError::GeoBlocked),
+    ("premieres in", VpodError::NotYetAvailable),
+    ("premiere will begin", VpodError::NotYetAvailable),
+    ("live event will begin", VpodError::NotYetAvailable),
+    ("has been removed", VpodError::VideoRemoved),
+    ("no longer available", VpodError::VideoRemoved),
+    (
+        "account associated with this video has been terminated",
+        VpodError::VideoRemoved,
+    ),
+    ("video unavailable", VpodError::VideoRemoved),
+    (
+        "requested format is not available",
+        VpodError::FormatUnavailable,
+    ),
+];
+
+const TRANSIENT: &[&str] = &[
+    "http error 429",
+    "http error 5",
+    "timed out",
+    "connection reset",
+    "connection refused",
+    "temporary failure in name resolution",
+    "unable to download webpage",
+    "unable to download video data",
+    "incomplete read",
+];
+
+/// Sort yt-dlp's stderr into a known failure, defaulting to a generic
+/// [`VpodError::YoutubeDLError`] when nothing matches.
+pub(crate) fn classify(stderr: &str) -> Failure {
+    let stderr = stderr.to_ascii_lowercase();
+
+    if let Some((_, error)) = PERMANENT.iter().find(|(needle, _)| stderr.contains(needle)) {
+        return Failure::Permanent(error.clone());
+    }
+
+    if TRANSIENT.iter().any(|needle| stderr.contains(needle)) {
+        Failure::Transient
+    } else {
+        Failure::Permanent(VpodError::YoutubeDLError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn permanent(stderr: &str) -> VpodError {
+        match classify(stderr) {
+            Failure::Permanent(e) => e,
+            Failure::Transient => panic!("{stderr} was classified as transient"),
+        }
+    }
+
+    #[test]
+    fn test_known_failures() {
+        assert!(matches!(
+            permanent("ERROR: [youtube] abc: Private video. Sign in if you've been granted access to this video"),
+            VpodError::VideoPrivate
+        ));
+        assert!(matches!(
+            permanent("ERROR: [youtube] abc: Join this channel to get access to members-only content like this video, and other exclusive perks."),
+            VpodError::MembersOnly
+        ));
+        assert!(matches!(
+            permanent("ERROR: [youtube] abc: Sign in to confirm your age. This video may be inappropriate for some users."),
+            VpodError::AgeRestricted
+        ));
+        assert!(matches!(
+            permanent("ERROR: [youtube] abc: The uploader has not made this video available in your country"),
+            VpodError::GeoBlocked
+        ));
+        assert!(matches!(
+            permanent("ERROR: [youtube] abc: Premieres in 3 hours"),
+            VpodError::NotYetAvailable
+        ));
+        assert!(matches!(
+            permanent("ERROR: [youtube] abc: Video unavailable. This video has been removed by the uploader"),
+            VpodError::VideoRemoved
+        ));
+        assert!(matches!(
+            permanent("ERROR: [youtube] abc: Requested format is not available. Use --list-formats for a list of available formats"),
+            VpodError::FormatUnavailable
+        ));
+        assert!(matches!(
+            permanent("ERROR: something nobody has seen before"),
+            VpodError::YoutubeDLError
+        ));
+    }
+
+    #[test]
+    fn test_transient_failures() {
+        assert!(matches!(
+            classify("ERROR: [youtube] abc: Unable to download webpage: HTTP Error 503: Service Unavailable"),
+            Failure::Transient
+        ));
+        assert!(matches!(
+            classify("ERROR: unable to download video data: The read operation timed out"),
+            Failure::Transient
+        ));
+    }
+}
@@ -0,0 +1,66 @@
+use std::{
+    fs,
+    path::Path,
+    process::{Command, Output},
+};
+
+use color_eyre::eyre::{eyre, WrapErr};
+
+use super::{probe, staged, AudioFormat, DownloadProfile};
+use crate::error::Result;
+
+/// Used when the sample rate of a file can't be probed.
+const FALLBACK_SAMPLE_RATE: u32 = 48_000;
+
+/// Run `filter` over the audio of `input` and encode the result to `output`
+/// in `format`, at the profile's bitrate and the input's sample rate.
+///
+/// The embedded thumbnail, chapters and tags are copied as they are.
+pub(crate) fn reencode(
+    input: &Path,
+    output: &Path,
+    filter: &str,
+    format: AudioFormat,
+    profile: &DownloadProfile,
+) -> Result<()> {
+    // Some filters resample, so ask for the original rate back.
+    let sample_rate = probe::sample_rate(input).unwrap_or(FALLBACK_SAMPLE_RATE);
+
+    let mut command = Command::new("ffmpeg");
+    command
+        .args(["-hide_banner", "-nostdin", "-y", "-i"])
+        .arg(input)
+        .args(["-map", "0", "-map_metadata", "0", "-c", "copy"])
+        .args(["-af", filter])
+        .args(["-c:a", format.ffmpeg_encoder()])
+        .args(["-ar", &sample_rate.to_string()]);
+    if let Some(max_bitrate) = profile.max_bitrate {
+        command.args(["-b:a", &format!("{max_bitrate}k")]);
+    }
+    run(command.arg(output))?;
+
+    Ok(())
+}
+
+/// Like [`reencode`], but replaces `path` with the result once it is complete.
+pub(crate) fn reencode_in_place(
+    path: &Path,
+    filter: &str,
+    format: AudioFormat,
+    profile: &DownloadProfile,
+) -> Result<()> {
+    let staged = staged(path)?;
+    reencode(path, &staged, filter, format, profile)?;
+    fs::rename(&staged, path).wrap_err("moving re-encoded episode into place")?;
+
+    Ok(())
+}
+
+pub(crate) fn run(command: &mut Command) -> Result<Output> {
+    let output = command.output().wrap_err("running ffmpeg")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(eyre!("ffmpeg failed: {}", stderr.trim()).into());
+    }
+    Ok(output)
+}
@@ -0,0 +1,85 @@
+use std::path::Path;
+
+use serde::Deserialize;
+
+/// Codec and container an episode is delivered in.
+///
+/// Anything yt-dlp downloads in another codec is converted by ffmpeg. All
+/// but [`AudioFormat::Mp4`] are audio only.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum AudioFormat {
+    /// AAC in an MP4 container, what YouTube mostly serves already.
+    #[default]
+    Aac,
+    /// Opus in an Ogg container, smallest for the same quality.
+    Opus,
+    /// MP3, for players that take nothing else.
+    Mp3,
+    /// Video with AAC audio in an MP4 container, for feeds where the picture
+    /// matters.
+    Mp4,
+}
+
+impl AudioFormat {
+    pub(crate) const ALL: [AudioFormat; 4] = [Self::Aac, Self::Opus, Self::Mp3, Self::Mp4];
+
+    pub(crate) fn extension(&self) -> &'static str {
+        match self {
+            Self::Aac => "m4a",
+            Self::Opus => "opus",
+            Self::Mp3 => "mp3",
+            Self::Mp4 => "mp4",
+        }
+    }
+
+    pub(crate) fn mime_type(&self) -> &'static str {
+        match self {
+            Self::Aac => "audio/x-m4a",
+            Self::Opus => "audio/ogg",
+            Self::Mp3 => "audio/mpeg",
+            Self::Mp4 => "video/mp4",
+        }
+    }
+
+    pub(crate) fn is_video(&self) -> bool {
+        *self == Self::Mp4
+    }
+
+    /// The value of yt-dlp's `--audio-format` for this format, or of
+    /// `--merge-output-format` for video.
+    pub(crate) fn ytdlp_format(&self) -> &'static str {
+        match self {
+            Self::Aac => "m4a",
+            Self::Opus => "opus",
+            Self::Mp3 => "mp3",
+            Self::Mp4 => "mp4",
+        }
+    }
+
+    /// The ffmpeg encoder used when an episode's audio has to be re-encoded.
+    pub(crate) fn ffmpeg_encoder(&self) -> &'static str {
+        match self {
+            Self::Aac | Self::Mp4 => "aac",
+            Self::Opus => "libopus",
+            Self::Mp3 => "libmp3lame",
+        }
+    }
+
+    /// Bytes per second assumed for an episode that hasn't been downloaded
+    /// yet, about what YouTube's streams come to.
+    pub(crate) fn estimated_bytes_per_sec(&self) -> u64 {
+        match self {
+            Self::Mp4 => 250_000,
+            _ => 16_000,
+        }
+    }
+
+    /// The format an episode file name asks for, going by its extension.
+    pub(crate) fn from_file_name(file_name: impl AsRef<Path>) -> Option<Self> {
+        let ext = file_name.as_ref().extension()?.to_str()?;
+        Self::ALL
+            .into_iter()
+            .find(|format| format.extension() == ext)
+    }
+}
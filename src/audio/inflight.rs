@@ -0,0 +1,175 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use futures::{
+    future::{BoxFuture, Shared},
+    FutureExt,
+};
+
+use crate::error::VpodError;
+
+type DownloadResult = std::result::Result<(), VpodError>;
+type SharedDownload = Shared<BoxFuture<'static, DownloadResult>>;
+
+/// Registry of episode downloads that are currently running.
+///
+/// Every request for the same file joins the one download already in flight
+/// instead of starting its own yt-dlp run. The download itself runs on its own
+/// task, so it carries on even if the client that started it goes away.
+#[derive(Clone, Default)]
+pub(crate) struct Downloads {
+    in_flight: Arc<Mutex<HashMap<PathBuf, SharedDownload>>>,
+}
+
+impl Downloads {
+    /// Make sure `path` exists, running `download` only if nobody else is
+    /// already producing it.
+    ///
+    /// `download` must only move the finished file to `path` once it is
+    /// complete, so a file that exists while no download is registered is
+    /// always safe to serve.
+    pub(crate) async fn fetch<F, Fut>(&self, path: &Path, download: F) -> DownloadResult
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = DownloadResult> + Send + 'static,
+    {
+        let shared = {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            match in_flight.get(path) {
+                Some(shared) => {
+                    tracing::debug!("joining download already in flight");
+                    shared.clone()
+                }
+                None if path.exists() => return Ok(()),
+                None => self.insert(&mut in_flight, path, download()),
+            }
+        };
+
+        shared.await
+    }
+
+    /// Start producing `path` with whatever `start` returns, unless the file
+    /// already exists or a download of it is already in flight.
+    ///
+    /// Returns whether this caller started the download. Later callers of
+    /// [`Downloads::fetch`] join it like any other.
+    pub(crate) fn start<F, Fut>(&self, path: &Path, start: F) -> Result<bool, VpodError>
+    where
+        F: FnOnce() -> Result<Fut, VpodError>,
+        Fut: Future<Output = DownloadResult> + Send + 'static,
+    {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if in_flight.contains_key(path) || path.exists() {
+            return Ok(false);
+        }
+
+        // The download runs on its own task, nobody has to wait on it here.
+        drop(self.insert(&mut in_flight, path, start()?));
+        Ok(true)
+    }
+
+    /// Rework `path` in place with whatever `start` returns, unless something
+    /// is already producing it. Unlike [`Downloads::start`] the file may exist.
+    ///
+    /// Callers of [`Downloads::fetch`] wait for the job like for a download.
+    pub(crate) fn redo<F, Fut>(&self, path: &Path, start: F) -> Result<bool, VpodError>
+    where
+        F: FnOnce() -> Result<Fut, VpodError>,
+        Fut: Future<Output = DownloadResult> + Send + 'static,
+    {
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if in_flight.contains_key(path) {
+            return Ok(false);
+        }
+
+        drop(self.insert(&mut in_flight, path, start()?));
+        Ok(true)
+    }
+
+    fn insert<Fut>(
+        &self,
+        in_flight: &mut HashMap<PathBuf, SharedDownload>,
+        path: &Path,
+        download: Fut,
+    ) -> SharedDownload
+    where
+        Fut: Future<Output = DownloadResult> + Send + 'static,
+    {
+        let shared = self.spawn(path.to_owned(), download);
+        in_flight.insert(path.to_owned(), shared.clone());
+        shared
+    }
+
+    fn spawn<Fut>(&self, path: PathBuf, download: Fut) -> SharedDownload
+    where
+        Fut: Future<Output = DownloadResult> + Send + 'static,
+    {
+        let in_flight = self.in_flight.clone();
+        let task = tokio::spawn(async move {
+            let result = download.await;
+            in_flight.lock().unwrap().remove(&path);
+            result
+        });
+
+        async move {
+            task.await.unwrap_or_else(|e| {
+                tracing::error!("download task failed: {e}");
+                Err(VpodError::YoutubeDLError)
+            })
+        }
+        .boxed()
+        .shared()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_concurrent_fetches_share_one_download() {
+        let downloads = Downloads::default();
+        let runs = Arc::new(AtomicUsize::new(0));
+        let path = PathBuf::from("does-not-exist/abc.m4a");
+
+        let fetches = (0..8).map(|_| {
+            let runs = runs.clone();
+            let downloads = downloads.clone();
+            let path = path.clone();
+            async move {
+                downloads
+                    .fetch(&path, move || async move {
+                        runs.fetch_add(1, Ordering::SeqCst);
+                        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                        Ok(())
+                    })
+                    .await
+            }
+        });
+
+        let results = futures::future::join_all(fetches).await;
+
+        assert!(results.iter().all(|r| r.is_ok()));
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        assert!(downloads.in_flight.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_failed_download_is_not_cached() {
+        let downloads = Downloads::default();
+        let path = PathBuf::from("does-not-exist/abc.m4a");
+
+        let first = downloads
+            .fetch(&path, || async { Err(VpodError::YoutubeDLError) })
+            .await;
+        let second = downloads.fetch(&path, || async { Ok(()) }).await;
+
+        assert!(first.is_err());
+        assert!(second.is_ok());
+    }
+}
@@ -0,0 +1,79 @@
+use std::{fs, path::Path};
+
+use color_eyre::eyre::eyre;
+use serde::{Deserialize, Serialize};
+
+use super::{probe, SilenceTrim};
+use crate::error::Result;
+use crate::feed::Store;
+
+/// What we know about a downloaded episode, kept in the store under its
+/// feed and file name.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub(crate) struct EpisodeMeta {
+    /// UUIDs of the SponsorBlock segments known when the file was made,
+    /// sorted. `None` if they could not be fetched.
+    pub(crate) sponsorblock_segments: Option<Vec<String>>,
+    /// Spans of the video, in seconds, cut out of the file as SponsorBlock
+    /// segments, merged and sorted. `None` unless segments are removed and
+    /// they could be fetched.
+    pub(crate) removed_spans: Option<Vec<(f64, f64)>>,
+    /// Size of the file in bytes.
+    pub(crate) size_bytes: Option<u64>,
+    /// Length of the audio as probed from the file.
+    pub(crate) duration_secs: Option<u32>,
+    /// Integrated loudness, in LUFS, the file was normalized to, if it was.
+    pub(crate) loudness_lufs: Option<f64>,
+    /// How silence was cut out of the file, if it was.
+    pub(crate) silence_trimmed: Option<SilenceTrim>,
+    /// Loudness the file last failed to be normalized to. It isn't tried
+    /// again until the feed asks for another.
+    pub(crate) failed_loudness_lufs: Option<f64>,
+    /// How silence last failed to be cut out of the file. It isn't tried
+    /// again until the feed asks for it another way.
+    pub(crate) failed_silence_trim: Option<SilenceTrim>,
+}
+
+impl EpisodeMeta {
+    /// Measure the finished file at `media`.
+    pub(crate) fn measure(media: &Path) -> Self {
+        Self {
+            size_bytes: fs::metadata(media).map(|metadata| metadata.len()).ok(),
+            duration_secs: probe::duration_secs(media),
+            ..Self::default()
+        }
+    }
+
+    pub(crate) fn load(store: &Store, media: &Path) -> Option<Self> {
+        let (feed_id, file_name) = key(media)?;
+        store
+            .download(feed_id, file_name)
+            .map_err(|e| tracing::warn!("could not load metadata for {}: {e:?}", media.display()))
+            .ok()
+            .flatten()
+    }
+
+    pub(crate) fn save(&self, store: &Store, media: &Path) -> Result<()> {
+        let (feed_id, file_name) = key(media).ok_or(eyre!("episode has no feed directory"))?;
+        store.save_download(feed_id, file_name, self)
+    }
+
+    /// Forget about `media` once it has been removed.
+    pub(crate) fn forget(store: &Store, media: &Path) {
+        let Some((feed_id, file_name)) = key(media) else {
+            return;
+        };
+        if let Err(e) = store.forget_download(feed_id, file_name) {
+            tracing::warn!("could not forget metadata for {}: {e:?}", media.display());
+        }
+    }
+}
+
+/// The feed and file name `media` is stored under, going by the feed
+/// directory it is in.
+fn key(media: &Path) -> Option<(&str, &str)> {
+    let file_name = media.file_name()?.to_str()?;
+    let feed_id = media.parent()?.file_name()?.to_str()?;
+    Some((feed_id, file_name))
+}
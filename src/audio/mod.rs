@@ -0,0 +1,1035 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use crate::config::FeedSettings;
+use crate::error::{Result, VpodError};
+use crate::feed::{self, Store};
+use crate::sponsorblock::{self, Segment};
+use crate::state::AppState;
+use crate::upstream::Upstream;
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::IntoResponse,
+};
+use color_eyre::eyre::eyre;
+use downloader::RunError;
+use failure::Failure;
+use tower::ServiceExt;
+
+mod cache;
+mod chapters;
+mod downloader;
+mod failure;
+mod ffmpeg;
+mod format;
+mod inflight;
+mod meta;
+mod normalize;
+mod pool;
+mod probe;
+mod profile;
+mod tee;
+mod tempo;
+mod transcript;
+mod trim;
+pub(crate) use cache::Cache;
+pub(crate) use chapters::SUFFIX as CHAPTERS_SUFFIX;
+#[cfg(test)]
+pub(crate) use downloader::FakeDownloader;
+pub(crate) use downloader::{Downloader, DownloaderKind};
+pub(crate) use format::AudioFormat;
+pub(crate) use inflight::Downloads;
+pub(crate) use meta::EpisodeMeta;
+pub(crate) use pool::DownloadPool;
+pub(crate) use profile::{DownloadProfile, SponsorBlock, SponsorCategory};
+pub(crate) use tempo::{EpisodeFile, TEMPOS};
+pub(crate) use transcript::is_unavailable as transcript_unavailable;
+pub(crate) use transcript::TranscriptFormat;
+pub(crate) use trim::SilenceTrim;
+
+/// Directory, relative to a feed's directory, that yt-dlp downloads into.
+/// Finished files are moved out of it so they are never served half-written.
+const STAGING_DIR: &str = ".incoming";
+
+/// How many times a download is attempted when yt-dlp fails transiently.
+const MAX_ATTEMPTS: u32 = 3;
+/// Wait before the first retry, doubled for every retry after it.
+const RETRY_BACKOFF: Duration = Duration::from_secs(2);
+
+#[tracing::instrument(skip(state), fields(feed_id=feed_id, episode_id=file_name))]
+pub async fn return_audio(
+    State(state): State<AppState>,
+    axum::extract::Path((feed_id, file_name)): axum::extract::Path<(String, String)>,
+    request: axum::extract::Request,
+) -> Result<impl IntoResponse> {
+    if !is_plain_name(&feed_id) || !is_episode_file_name(&file_name) {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+    if let Some(stem) = file_name.strip_suffix(chapters::SUFFIX) {
+        return chapters::serve_chapters(&state, &feed_id, stem, request).await;
+    }
+    if let Some((stem, format)) = TranscriptFormat::split_file_name(&file_name) {
+        return transcript::serve_transcript(&state, &feed_id, stem, format, request).await;
+    }
+
+    let settings = state.config.feed(&feed_id).clone();
+    let Some(file) = EpisodeFile::parse(&file_name) else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    if file
+        .tempo
+        .is_some_and(|tempo| !settings.offers_tempo(tempo))
+    {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+    let ep_id = file.ep_id.to_owned();
+    let format = file.format;
+    let feed_dir = state.feed_dir(&feed_id);
+    let path = feed_dir.join(&file_name);
+    let base = feed_dir.join(file.base().file_name());
+    let profile = state.config.profile(&feed_id);
+    // Only the feed's own format is downloaded. Others are served while they
+    // are still cached from before the feed changed format, except video on
+    // a feed that isn't delivered as video.
+    if format != profile.codec && (format.is_video() || !base.exists()) {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+
+    // Only AAC comes straight out of yt-dlp without needing ffmpeg.
+    if file.tempo.is_none() && profile.stream_first_download && format == AudioFormat::Aac {
+        let (chunks, listener) = tokio::sync::mpsc::channel(tee::CHANNEL_CHUNKS);
+        let started = state.downloads.start(&path, || {
+            let ep_id = ep_id.clone();
+            let path = path.clone();
+            let profile = profile.clone();
+            let settings = settings.clone();
+            let store = state.store.clone();
+            let cache = state.cache.clone();
+            let downloader = state.downloader.clone();
+            let job = state.pool.submit(move || {
+                tee::tee(&store, &*downloader, &ep_id, &path, &profile, chunks)?;
+                // Only later listeners get to hear the post-processed file.
+                post_process(&store, &path, &settings, format, &profile);
+                if let Some(language) = settings.transcripts() {
+                    // Segments are never removed from streamed downloads.
+                    fetch_transcript(&*downloader, &ep_id, &path, language, None, &profile);
+                }
+                if let Err(e) = cache.sweep() {
+                    tracing::error!("could not sweep the episode cache: {e:?}");
+                }
+                Ok(())
+            })?;
+            Ok(job)
+        })?;
+
+        // Headers wait for the first chunk, so a download that fails right
+        // away is answered with its own status rather than an empty 200.
+        if started {
+            if let Some(body) = tee::first_listener_body(listener).await? {
+                return Ok(([(header::CONTENT_TYPE, mime_type(&file_name))], body).into_response());
+            }
+        }
+    }
+
+    state
+        .downloads
+        .fetch(&base, || {
+            let pool = state.pool.clone();
+            let store = state.store.clone();
+            let cache = state.cache.clone();
+            let downloader = state.downloader.clone();
+            let api = state.sponsorblock_api.clone();
+            let base = base.clone();
+            let profile = profile.clone();
+            let settings = settings.clone();
+            async move {
+                let segments = fetch_segments(&api, &ep_id, &profile.sponsorblock).await;
+                pool.run(move || {
+                    download(&*downloader, &ep_id, &base, &profile, format, &cache)?;
+                    let removed_spans = segments
+                        .as_deref()
+                        .filter(|_| profile.sponsorblock.removes_segments())
+                        .map(sponsorblock::merged);
+                    let meta = EpisodeMeta {
+                        sponsorblock_segments: segments.as_deref().map(sponsorblock::uuids),
+                        removed_spans,
+                        ..EpisodeMeta::measure(&base)
+                    };
+                    if let Err(e) = meta.save(&store, &base) {
+                        tracing::warn!("could not save episode metadata: {e:?}");
+                    }
+                    post_process(&store, &base, &settings, format, &profile);
+                    if let Some(language) = settings.transcripts() {
+                        fetch_transcript(&*downloader, &ep_id, &base, language, segments, &profile);
+                    }
+                    Ok(())
+                })
+                .await
+            }
+        })
+        .await?;
+    state.cache.touch(&base);
+
+    // Files cached before the feed asked for post-processing are caught up in
+    // the background, a request for the file itself still gets it as it is.
+    let needs_post_processing = {
+        let base = base.clone();
+        let settings = settings.clone();
+        state
+            .store
+            .blocking(move |store| Ok(needs_post_processing(store, &base, &settings)))
+            .await?
+    };
+    if needs_post_processing {
+        let started = state.downloads.redo(&base, || {
+            let base = base.clone();
+            let profile = profile.clone();
+            let settings = settings.clone();
+            let store = state.store.clone();
+            state.pool.submit(move || {
+                post_process(&store, &base, &settings, format, &profile);
+                remove_variants(&store, &base);
+                Ok(())
+            })
+        });
+        match started {
+            // Variants are made from the processed file, so wait for it.
+            Ok(_) if file.tempo.is_some() => {
+                state.downloads.fetch(&base, || async { Ok(()) }).await?
+            }
+            Ok(_) => (),
+            Err(e) => tracing::warn!("could not queue post-processing: {e}"),
+        }
+    }
+
+    if let Some(tempo) = file.tempo {
+        state
+            .downloads
+            .fetch(&path, || {
+                let pool = state.pool.clone();
+                let store = state.store.clone();
+                let path = path.clone();
+                async move {
+                    pool.run(move || {
+                        tempo::make_variant(&store, &base, &path, tempo, format, &profile).map_err(
+                            |e| {
+                                tracing::error!("could not make {tempo}x variant: {e:?}");
+                                VpodError::YoutubeDLError
+                            },
+                        )
+                    })
+                    .await
+                }
+            })
+            .await?;
+        state.cache.touch(&path);
+    }
+
+    let service = tower_http::services::ServeFile::new(&path);
+
+    let result = service.oneshot(request).await;
+
+    Ok(result.into_response())
+}
+
+/// Answer HEAD for an episode from what is already known about it, without
+/// ever starting a download.
+///
+/// A downloaded file reports its real size. Otherwise the size is the
+/// estimate from the stored feed.
+#[tracing::instrument(skip(state), fields(feed_id=feed_id, episode_id=file_name))]
+pub async fn head_audio(
+    State(state): State<AppState>,
+    axum::extract::Path((feed_id, file_name)): axum::extract::Path<(String, String)>,
+) -> Result<impl IntoResponse> {
+    if !is_plain_name(&feed_id) || !is_episode_file_name(&file_name) {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+    let feed_dir = state.feed_dir(&feed_id);
+    let path = feed_dir.join(&file_name);
+
+    let length = match tokio::fs::metadata(&path).await {
+        Ok(metadata) => Some(metadata.len()),
+        Err(_) => stored_length(&state, &feed_id, &file_name).await,
+    };
+
+    let Some(length) = length else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, mime_type(&file_name).to_string()),
+            (header::CONTENT_LENGTH, length.to_string()),
+            (header::ACCEPT_RANGES, "bytes".to_string()),
+        ],
+        (),
+    )
+        .into_response())
+}
+
+/// Enclosure length of `file_name` in the stored feed. Sped-up variants are
+/// estimated from the episode at normal speed.
+async fn stored_length(state: &AppState, feed_id: &str, file_name: &str) -> Option<u64> {
+    let file = EpisodeFile::parse(file_name)?;
+    let length = feed::stored_enclosure_length(state, feed_id, &file.base().file_name()).await?;
+
+    Some(match file.tempo {
+        Some(tempo) => (length as f64 / tempo) as u64,
+        None => length,
+    })
+}
+
+/// Whether `name` is a single normal path component, and not a hidden one
+/// like the staging directory. Anything else joined onto a directory could
+/// reach files outside of it.
+fn is_plain_name(name: &str) -> bool {
+    let mut components = Path::new(name).components();
+    matches!(
+        (components.next(), components.next()),
+        (Some(std::path::Component::Normal(_)), None)
+    ) && !name.starts_with('.')
+}
+
+/// Whether `file_name` is a plain name of one of the files served for an
+/// episode: the episode itself, its chapters or a transcript, named after
+/// a video ID.
+fn is_episode_file_name(file_name: &str) -> bool {
+    if !is_plain_name(file_name) {
+        return false;
+    }
+    let stem = file_name
+        .strip_suffix(chapters::SUFFIX)
+        .or_else(|| TranscriptFormat::split_file_name(file_name).map(|(stem, _)| stem));
+    match stem {
+        Some(stem) => EpisodeFile::parse_stem(stem, AudioFormat::default()).is_some(),
+        None => EpisodeFile::parse(file_name).is_some(),
+    }
+}
+
+/// MIME type of an episode file, going by its extension.
+pub(crate) fn mime_type(file_name: &str) -> &'static str {
+    AudioFormat::from_file_name(file_name)
+        .map(|format| format.mime_type())
+        .unwrap_or("application/octet-stream")
+}
+
+/// Trim silence from and normalize the finished episode at `path`, as far
+/// as its feed asks for either. A step that fails leaves the file as it was,
+/// and is recorded so it isn't tried again with the same settings.
+fn post_process(
+    store: &Store,
+    path: &Path,
+    settings: &FeedSettings,
+    format: AudioFormat,
+    profile: &DownloadProfile,
+) {
+    if let Some(trim) = &settings.trim_silence {
+        if let Err(e) = trim::trim_silence_once(store, path, trim, format, profile) {
+            tracing::error!("could not trim silence: {e:?}");
+            record_failure(store, path, |meta| {
+                meta.failed_silence_trim = Some(trim.clone())
+            });
+        }
+    }
+    if let Some(target_lufs) = settings.loudness_target_lufs {
+        if let Err(e) = normalize::normalize_once(store, path, target_lufs, format, profile) {
+            tracing::error!("could not normalize loudness: {e:?}");
+            record_failure(store, path, |meta| {
+                meta.failed_loudness_lufs = Some(target_lufs)
+            });
+        }
+    }
+}
+
+/// Note in the metadata of `path` which post-processing step failed.
+fn record_failure(store: &Store, path: &Path, failed: impl FnOnce(&mut EpisodeMeta)) {
+    let mut meta = EpisodeMeta::load(store, path).unwrap_or_default();
+    failed(&mut meta);
+    if let Err(e) = meta.save(store, path) {
+        tracing::warn!("could not save episode metadata: {e:?}");
+    }
+}
+
+/// Fetch the transcript of the episode downloaded to `base` along with it,
+/// unless it has been already. An episode without one is still served.
+fn fetch_transcript(
+    downloader: &dyn Downloader,
+    ep_id: &str,
+    base: &Path,
+    language: &str,
+    segments: Option<Vec<Segment>>,
+    profile: &DownloadProfile,
+) {
+    let vtt = base.with_extension(TranscriptFormat::Vtt.extension());
+    if vtt.exists() {
+        return;
+    }
+    let segments = segments.unwrap_or_default();
+    let fetched = transcript::fetch(
+        downloader,
+        ep_id,
+        &vtt,
+        language,
+        &segments,
+        &profile.sponsorblock,
+        None,
+    );
+    if let Err(e) = fetched {
+        tracing::warn!(reason = e.reason(), "could not fetch transcript");
+    }
+}
+
+/// Whether the episode at `path` is missing post-processing its feed asks
+/// for. Steps that already failed with the same settings don't count, the
+/// file is served as it is instead.
+fn needs_post_processing(store: &Store, path: &Path, settings: &FeedSettings) -> bool {
+    let meta = EpisodeMeta::load(store, path).unwrap_or_default();
+    let untrimmed = settings.trim_silence.as_ref().is_some_and(|trim| {
+        meta.silence_trimmed.as_ref() != Some(trim)
+            && meta.failed_silence_trim.as_ref() != Some(trim)
+    });
+    let unnormalized = settings.loudness_target_lufs.is_some_and(|target| {
+        meta.loudness_lufs != Some(target) && meta.failed_loudness_lufs != Some(target)
+    });
+
+    untrimmed || unnormalized
+}
+
+/// Remove every sped-up variant made from the episode at `base`, and their
+/// metadata, so they are made again from what `base` is now.
+fn remove_variants(store: &Store, base: &Path) {
+    let (Some(dir), Some(base_name)) = (base.parent(), base.file_name()) else {
+        return;
+    };
+    let Some(base) = base_name.to_str().and_then(EpisodeFile::parse) else {
+        return;
+    };
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+
+    for path in entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+    {
+        let is_variant = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(EpisodeFile::parse)
+            .is_some_and(|file| file.tempo.is_some() && file.base() == base);
+        if is_variant {
+            tracing::debug!("removing stale variant {}", path.display());
+            let _ = fs::remove_file(&path);
+            EpisodeMeta::forget(store, &path);
+        }
+    }
+}
+
+/// The segments yt-dlp is about to act on, if it acts on any. `None` if
+/// they can't be fetched.
+async fn fetch_segments(
+    api: &Upstream,
+    ep_id: &str,
+    sponsorblock: &SponsorBlock,
+) -> Option<Vec<Segment>> {
+    if !sponsorblock.is_enabled() {
+        return Some(vec![]);
+    }
+
+    sponsorblock::segments(api, ep_id, &sponsorblock.categories)
+        .await
+        .map_err(|e| tracing::warn!("could not get SponsorBlock segments: {e:?}"))
+        .ok()
+}
+
+/// The UUIDs of the segments yt-dlp is about to act on, if it acts on any.
+async fn current_segments(
+    api: &Upstream,
+    ep_id: &str,
+    sponsorblock: &SponsorBlock,
+) -> Option<Vec<String>> {
+    fetch_segments(api, ep_id, sponsorblock)
+        .await
+        .map(|segments| sponsorblock::uuids(&segments))
+}
+
+/// Drop the cached `file_name` in `feed_dir` if the SponsorBlock segments of
+/// its video changed since it was downloaded, so the next request redoes it.
+#[tracing::instrument(skip(store, api, sponsorblock))]
+pub(crate) async fn recheck_segments(
+    store: &Store,
+    api: &Upstream,
+    feed_dir: &Path,
+    file_name: &str,
+    sponsorblock: &SponsorBlock,
+) -> Result<()> {
+    let path = feed_dir.join(file_name);
+    let ep_id = file_name.split('.').next().unwrap_or(file_name);
+
+    let recorded = {
+        let path = path.clone();
+        store
+            .blocking(move |store| Ok(EpisodeMeta::load(store, &path)))
+            .await?
+    };
+    let Some(recorded) = recorded.and_then(|meta| meta.sponsorblock_segments) else {
+        return Ok(());
+    };
+    let Some(current) = current_segments(api, ep_id, sponsorblock).await else {
+        return Ok(());
+    };
+
+    if current != recorded {
+        tracing::info!(
+            episode_id = ep_id,
+            "SponsorBlock segments changed, dropping cached audio"
+        );
+        let feed_dir = feed_dir.to_owned();
+        let ep_id = ep_id.to_owned();
+        store
+            .blocking(move |store| {
+                fs::remove_file(&path)?;
+                EpisodeMeta::forget(store, &path);
+                remove_variants(store, &path);
+                transcript::remove(&feed_dir, &ep_id);
+                chapters::remove(&feed_dir, &ep_id);
+                Ok(())
+            })
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Where `path` is written before it is moved into place.
+fn staged(path: &Path) -> Result<PathBuf> {
+    let staging_dir = path
+        .parent()
+        .ok_or(eyre!("episode has no feed directory"))?
+        .join(STAGING_DIR);
+    fs::create_dir_all(&staging_dir)?;
+
+    Ok(staging_dir.join(path.file_name().ok_or(eyre!("episode has no file name"))?))
+}
+
+/// Run the downloader through `run`, retrying transient failures with a
+/// growing backoff, and return what it returned.
+fn retrying<T>(run: impl Fn() -> Result<T, RunError>) -> Result<T, VpodError> {
+    let mut backoff = RETRY_BACKOFF;
+    for attempt in 1..=MAX_ATTEMPTS {
+        let stderr = match run() {
+            Ok(output) => return Ok(output),
+            Err(RunError::Failed(stderr)) => stderr,
+            Err(RunError::Unavailable(e)) => {
+                tracing::error!("could not run yt-dlp: {e}");
+                return Err(VpodError::YoutubeDLError);
+            }
+        };
+
+        match failure::classify(&stderr) {
+            Failure::Transient if attempt < MAX_ATTEMPTS => {
+                tracing::warn!(attempt, stderr = stderr.trim(), "yt-dlp failed, retrying");
+                thread::sleep(backoff);
+                backoff *= 2;
+            }
+            Failure::Transient => {
+                tracing::error!(attempt, stderr = stderr.trim(), "yt-dlp failed, giving up");
+                return Err(VpodError::YoutubeDLError);
+            }
+            Failure::Permanent(e) => {
+                tracing::warn!(reason = e.reason(), stderr = stderr.trim(), "yt-dlp failed");
+                return Err(e);
+            }
+        }
+    }
+
+    Err(VpodError::YoutubeDLError)
+}
+
+/// Ask the downloader about `ep_id` without downloading anything, running
+/// it in `dir`.
+pub(crate) fn video_info(
+    downloader: &dyn Downloader,
+    ep_id: &str,
+    dir: &Path,
+) -> Result<String, VpodError> {
+    retrying(|| downloader.video_info(ep_id, dir))
+}
+
+#[tracing::instrument(skip(downloader, profile, cache))]
+fn download(
+    downloader: &dyn Downloader,
+    ep_id: &str,
+    path: &Path,
+    profile: &DownloadProfile,
+    format: AudioFormat,
+    cache: &Cache,
+) -> Result<(), VpodError> {
+    let channel_dir = path.parent().ok_or(VpodError::YoutubeDLError)?;
+    let staging_dir = channel_dir.join(STAGING_DIR);
+    let file_name = path.file_name().ok_or(VpodError::YoutubeDLError)?;
+
+    retrying(|| downloader.download(ep_id, &staging_dir, profile, format))?;
+
+    fs::rename(staging_dir.join(file_name), path).map_err(|e| {
+        tracing::error!("could not move finished download into place: {e}");
+        VpodError::YoutubeDLError
+    })?;
+
+    if let Err(e) = cache.sweep() {
+        tracing::error!("could not sweep the episode cache: {e:?}");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::{body::Body, http::Request, Router};
+
+    use super::downloader::FAKE_EPISODE_BYTES;
+    use super::*;
+    use crate::state::test_state;
+
+    /// Feeds without SponsorBlock, so nothing reaches the network.
+    const OFFLINE_CONFIG: &str = r#"
+        [profiles.default]
+        sponsorblock = { mode = "off" }
+
+        [profiles.streamed]
+        sponsorblock = { mode = "off" }
+        stream_first_download = true
+
+        [feeds.streamed]
+        profile = "streamed"
+        transcript_language = "en"
+
+        [feeds.uncaptioned]
+        transcript_language = "fr"
+
+        [feeds.normalized]
+        loudness_target_lufs = -16
+
+        [feeds.video]
+        video = true
+
+        [feeds.trimmed]
+        trim_silence = { threshold_db = -50 }
+        transcript_language = "en"
+
+        [profiles.cut]
+        sponsorblock = { mode = "remove" }
+
+        [feeds.cut]
+        profile = "cut"
+        "#;
+
+    /// The whole app over a fresh data directory named after `test`,
+    /// downloading with `fake`.
+    async fn app(test: &str, fake: &Arc<FakeDownloader>, args: &[&str]) -> (Router, PathBuf) {
+        let (mut state, data_dir) = test_state(test, OFFLINE_CONFIG, args).await;
+        state.downloader = fake.clone();
+
+        (crate::router(state), data_dir)
+    }
+
+    async fn get(app: &Router, uri: &str) -> (StatusCode, Vec<u8>) {
+        let response = app
+            .clone()
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap_or_default();
+        (status, body.to_vec())
+    }
+
+    #[tokio::test]
+    async fn test_episode_is_downloaded_once_and_served() {
+        let fake = Arc::new(FakeDownloader::default());
+        let (app, data_dir) = app("serve", &fake, &[]).await;
+
+        for _ in 0..2 {
+            let (status, body) = get(&app, "/ep/feed/abc00000000.m4a").await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(body, FakeDownloader::episode("abc00000000"));
+        }
+        assert_eq!(fake.downloads(), 1);
+        assert!(data_dir.join("feed/abc00000000.m4a").exists());
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::head("/ep/feed/abc00000000.m4a")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_LENGTH],
+            FAKE_EPISODE_BYTES.to_string()
+        );
+
+        let (status, body) = get(&app, "/ep/feed/abc00000000.chapters.json").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(String::from_utf8(body).unwrap().contains("Middle"));
+
+        fs::remove_dir_all(&data_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_first_download_is_streamed_and_cached() {
+        let fake = Arc::new(FakeDownloader::default());
+        let (app, data_dir) = app("stream", &fake, &[]).await;
+
+        let (status, body) = get(&app, "/ep/streamed/abc00000000.m4a").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, FakeDownloader::episode("abc00000000"));
+
+        // The stream ends once the last chunk is handed on, the file lands
+        // in the cache right after.
+        state_settles(|| data_dir.join("streamed/abc00000000.m4a").exists()).await;
+        let (status, body) = get(&app, "/ep/streamed/abc00000000.m4a").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, FakeDownloader::episode("abc00000000"));
+        assert_eq!(fake.downloads(), 1);
+
+        // Its transcript was fetched along with it.
+        state_settles(|| data_dir.join("streamed/abc00000000.vtt").exists()).await;
+        let (status, body) = get(&app, "/ep/streamed/abc00000000.vtt").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(String::from_utf8(body)
+            .unwrap()
+            .contains("hello from the fixture"));
+        assert_eq!(fake.subtitle_runs(), 1);
+
+        fs::remove_dir_all(&data_dir).unwrap();
+    }
+
+    async fn state_settles(done: impl Fn() -> bool) {
+        for _ in 0..100 {
+            if done() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("gave up waiting");
+    }
+
+    #[tokio::test]
+    async fn test_failures_are_mapped_to_statuses() {
+        let fake = Arc::new(
+            FakeDownloader::default()
+                .failing("private0000", "ERROR: [youtube] private0000: Private video")
+                .failing(
+                    "removed0000",
+                    "ERROR: [youtube] removed0000: This video has been removed",
+                )
+                .failing(
+                    "format00000",
+                    "ERROR: [youtube] format00000: Requested format is not available",
+                ),
+        );
+        let (app, data_dir) = app("failures", &fake, &[]).await;
+
+        // Streamed downloads hold their headers back until they know.
+        for feed in ["feed", "streamed"] {
+            for (ep_id, expected) in [
+                ("private0000", StatusCode::FORBIDDEN),
+                ("removed0000", StatusCode::GONE),
+                ("format00000", StatusCode::BAD_GATEWAY),
+            ] {
+                let (status, _) = get(&app, &format!("/ep/{feed}/{ep_id}.m4a")).await;
+                assert_eq!(status, expected, "{feed}/{ep_id}");
+                assert!(!data_dir.join(format!("{feed}/{ep_id}.m4a")).exists());
+            }
+        }
+        assert_eq!(fake.downloads(), 0);
+
+        fs::remove_dir_all(&data_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_chapters_follow_the_segments_cut_from_the_file() {
+        let fake = Arc::new(FakeDownloader::default());
+        let (mut state, data_dir) = test_state("cut-chapters", OFFLINE_CONFIG, &[]).await;
+        state.downloader = fake.clone();
+        let app = crate::router(state.clone());
+        let uri = "/ep/cut/abc00000000.chapters.json";
+
+        // Nothing is known about what was cut before the file is downloaded.
+        assert_eq!(get(&app, uri).await.0, StatusCode::NOT_FOUND);
+        assert!(!data_dir.join("cut/abc00000000.chapters.json").exists());
+
+        let meta = EpisodeMeta {
+            removed_spans: Some(vec![(30.0, 50.0)]),
+            ..EpisodeMeta::default()
+        };
+        meta.save(&state.store, &data_dir.join("cut/abc00000000.m4a"))
+            .unwrap();
+        let (status, body) = get(&app, uri).await;
+        assert_eq!(status, StatusCode::OK);
+        let chapters: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let starts: Vec<f64> = chapters["chapters"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|chapter| chapter["startTime"].as_f64().unwrap())
+            .collect();
+        assert_eq!(starts, [0.0, 40.0, 100.0]);
+
+        fs::remove_dir_all(&data_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_only_the_feeds_format_is_downloaded() {
+        let fake = Arc::new(FakeDownloader::default());
+        let (app, data_dir) = app("formats", &fake, &[]).await;
+
+        for uri in ["/ep/feed/abc00000000.opus", "/ep/feed/abc00000000.mp4"] {
+            assert_eq!(get(&app, uri).await.0, StatusCode::NOT_FOUND, "{uri}");
+        }
+        assert_eq!(fake.downloads(), 0);
+
+        // Files left from before a feed changed format are still served,
+        // apart from video.
+        fs::create_dir_all(data_dir.join("feed")).unwrap();
+        for file_name in ["abc00000000.opus", "abc00000000.mp4"] {
+            fs::write(data_dir.join("feed").join(file_name), b"cached").unwrap();
+        }
+        let (status, body) = get(&app, "/ep/feed/abc00000000.opus").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, b"cached");
+        let (status, _) = get(&app, "/ep/feed/abc00000000.mp4").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, _) = get(&app, "/ep/video/abc00000000.mp4").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(fake.downloads(), 1);
+
+        fs::remove_dir_all(&data_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_trimmed_episodes_have_no_chapters_or_transcripts() {
+        let fake = Arc::new(FakeDownloader::default());
+        let (app, data_dir) = app("trimmed", &fake, &[]).await;
+
+        for uri in [
+            "/ep/trimmed/abc00000000.chapters.json",
+            "/ep/trimmed/abc00000000.vtt",
+            "/ep/trimmed/abc00000000.srt",
+        ] {
+            assert_eq!(get(&app, uri).await.0, StatusCode::NOT_FOUND, "{uri}");
+        }
+        assert_eq!(fake.downloads(), 0);
+        assert_eq!(fake.subtitle_runs(), 0);
+
+        fs::remove_dir_all(&data_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_missing_captions_are_remembered() {
+        let fake = Arc::new(FakeDownloader::default());
+        let (app, data_dir) = app("no-captions", &fake, &[]).await;
+
+        for uri in [
+            "/ep/uncaptioned/abc00000000.vtt",
+            "/ep/uncaptioned/abc00000000.srt",
+        ] {
+            let (status, _) = get(&app, uri).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{uri}");
+        }
+        // yt-dlp was only asked the first time.
+        assert_eq!(fake.subtitle_runs(), 1);
+        let feed_dir = data_dir.join("uncaptioned");
+        assert!(transcript_unavailable(&feed_dir, "abc00000000", "fr"));
+        assert!(!feed_dir.join(".incoming/abc00000000.captions").exists());
+
+        fs::remove_dir_all(&data_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_paths_outside_the_data_dir_are_rejected() {
+        let fake = Arc::new(FakeDownloader::default());
+        let (app, data_dir) = app("outside", &fake, &[]).await;
+        // A file an absolute name would reach if it were joined as it is.
+        let outside = data_dir.with_extension("m4a");
+        fs::write(&outside, "secret").unwrap();
+        let outside = outside.to_str().unwrap().replace('/', "%2F");
+
+        let mut uris = vec![];
+        for feed_id in ["..", "%2E%2E", "a%2Fb", "%2Fetc", ".incoming"] {
+            for file_name in [
+                "abc00000000.m4a",
+                "abc00000000.chapters.json",
+                "abc00000000.vtt",
+            ] {
+                uris.push(format!("/ep/{feed_id}/{file_name}"));
+            }
+        }
+        for file_name in [
+            outside.as_str(),
+            "%2Fetc%2Fpasswd",
+            "..",
+            "%2E%2E",
+            "..%2Fabc00000000.m4a",
+            "%2E%2E%2Fabc00000000.m4a",
+            ".abc0000000.m4a",
+            "abc.m4a",
+        ] {
+            uris.push(format!("/ep/feed/{file_name}"));
+        }
+
+        for uri in uris {
+            let (status, _) = get(&app, &uri).await;
+            assert_eq!(status, StatusCode::NOT_FOUND, "{uri}");
+
+            let response = app
+                .clone()
+                .oneshot(Request::head(&uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{uri}");
+        }
+        assert_eq!(fake.downloads(), 0);
+
+        fs::remove_file(data_dir.with_extension("m4a")).unwrap();
+        fs::remove_dir_all(&data_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_chapter_stems_outside_the_feed_are_rejected() {
+        let fake = Arc::new(FakeDownloader::default());
+        let (mut state, data_dir) = test_state("chapters-stem", OFFLINE_CONFIG, &[]).await;
+        state.downloader = fake.clone();
+        let app = crate::router(state.clone());
+
+        let stems = [
+            "../abc00000000",
+            "/tmp/abc00000000",
+            "abc00000000/..",
+            ".abc0000000",
+        ];
+        for stem in stems {
+            let request = Request::get("/").body(Body::empty()).unwrap();
+            let response = chapters::serve_chapters(&state, "feed", stem, request)
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{stem}");
+
+            let uri = format!("/ep/feed/{}.chapters.json", stem.replace('/', "%2F"));
+            assert_eq!(get(&app, &uri).await.0, StatusCode::NOT_FOUND, "{uri}");
+        }
+        assert!(!data_dir.join("feed").exists());
+
+        fs::remove_dir_all(&data_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_transcript_stems_outside_the_feed_are_rejected() {
+        let fake = Arc::new(FakeDownloader::default());
+        let (mut state, data_dir) = test_state("transcript-stem", OFFLINE_CONFIG, &[]).await;
+        state.downloader = fake.clone();
+        let app = crate::router(state.clone());
+
+        let stems = [
+            "../abc00000000",
+            "/tmp/abc00000000",
+            "abc00000000/..",
+            ".abc0000000",
+        ];
+        for stem in stems {
+            for format in TranscriptFormat::ALL {
+                let request = Request::get("/").body(Body::empty()).unwrap();
+                let response =
+                    transcript::serve_transcript(&state, "streamed", stem, format, request)
+                        .await
+                        .unwrap();
+                assert_eq!(response.status(), StatusCode::NOT_FOUND, "{stem}");
+
+                let uri = format!(
+                    "/ep/streamed/{}.{}",
+                    stem.replace('/', "%2F"),
+                    format.extension()
+                );
+                assert_eq!(get(&app, &uri).await.0, StatusCode::NOT_FOUND, "{uri}");
+            }
+        }
+        assert_eq!(fake.subtitle_runs(), 0);
+        assert!(!data_dir.join("streamed").exists());
+
+        fs::remove_dir_all(&data_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_failed_post_processing_is_not_retried() {
+        let fake = Arc::new(FakeDownloader::default());
+        let (mut state, data_dir) = test_state("post-process", OFFLINE_CONFIG, &[]).await;
+        state.downloader = fake.clone();
+        let app = crate::router(state.clone());
+
+        // The fake episode isn't audio ffmpeg can normalize.
+        for _ in 0..2 {
+            let (status, body) = get(&app, "/ep/normalized/abc00000000.m4a").await;
+            assert_eq!(status, StatusCode::OK);
+            assert_eq!(body, FakeDownloader::episode("abc00000000"));
+        }
+
+        let path = data_dir.join("normalized/abc00000000.m4a");
+        let meta = EpisodeMeta::load(&state.store, &path).unwrap();
+        assert_eq!(meta.loudness_lufs, None);
+        assert_eq!(meta.failed_loudness_lufs, Some(-16.0));
+        let settings = state.config.feed("normalized");
+        assert!(!needs_post_processing(&state.store, &path, settings));
+
+        // Another target is tried again.
+        let settings = FeedSettings {
+            loudness_target_lufs: Some(-20.0),
+            ..settings.clone()
+        };
+        assert!(needs_post_processing(&state.store, &path, &settings));
+
+        fs::remove_dir_all(&data_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_least_recently_served_episode_is_evicted() {
+        let fake = Arc::new(FakeDownloader::default());
+        let budget = (FAKE_EPISODE_BYTES * 5 / 2).to_string();
+        let (app, data_dir) = app(
+            "evict",
+            &fake,
+            &["--cache-max-bytes", &budget, "--cache-min-free-bytes", "0"],
+        )
+        .await;
+
+        for ep_id in ["one00000000", "two00000000", "three000000"] {
+            let (status, _) = get(&app, &format!("/ep/feed/{ep_id}.m4a")).await;
+            assert_eq!(status, StatusCode::OK);
+        }
+        assert!(!data_dir.join("feed/one00000000.m4a").exists());
+        assert!(data_dir.join("feed/two00000000.m4a").exists());
+        assert!(data_dir.join("feed/three000000.m4a").exists());
+
+        assert_eq!(
+            get(&app, "/ep/feed/one00000000.m4a").await.0,
+            StatusCode::OK
+        );
+        assert_eq!(fake.downloads(), 4);
+
+        fs::remove_dir_all(&data_dir).unwrap();
+    }
+}
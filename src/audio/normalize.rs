@@ -0,0 +1,127 @@
+use std::{fs, path::Path, process::Command};
+
+use color_eyre::eyre::{eyre, WrapErr};
+use serde::Deserialize;
+
+use super::{ffmpeg, AudioFormat, DownloadProfile, EpisodeMeta};
+use crate::error::Result;
+use crate::feed::Store;
+
+/// Highest true peak, in dBTP, a normalized episode may reach.
+const TRUE_PEAK: f64 = -1.5;
+/// Loudness range, in LU, loudnorm aims for if it can't normalize linearly.
+const LOUDNESS_RANGE: f64 = 11.0;
+
+/// What the first loudnorm pass measured, to feed into the second.
+#[derive(Debug, Deserialize)]
+struct Measured {
+    input_i: String,
+    input_tp: String,
+    input_lra: String,
+    input_thresh: String,
+    target_offset: String,
+}
+
+/// Normalize the episode at `path` to `target_lufs` with ffmpeg's EBU R128
+/// `loudnorm` filter, unless its metadata says that was done already.
+///
+/// Takes two passes: one to measure the file, one to correct it with what
+/// was measured, so quiet and loud stretches keep their relation to each
+/// other. The file is re-encoded in its own format and replaced atomically.
+#[tracing::instrument(skip(store, profile))]
+pub(crate) fn normalize_once(
+    store: &Store,
+    path: &Path,
+    target_lufs: f64,
+    format: AudioFormat,
+    profile: &DownloadProfile,
+) -> Result<()> {
+    let mut meta = EpisodeMeta::load(store, path).unwrap_or_default();
+    if meta.loudness_lufs == Some(target_lufs) {
+        tracing::debug!("already normalized");
+        return Ok(());
+    }
+
+    let measured = measure(path, target_lufs)?;
+    tracing::debug!(input_lufs = measured.input_i, "measured loudness");
+
+    let filter = format!(
+        "{}:measured_I={}:measured_TP={}:measured_LRA={}:measured_thresh={}:offset={}:linear=true",
+        loudnorm(target_lufs),
+        measured.input_i,
+        measured.input_tp,
+        measured.input_lra,
+        measured.input_thresh,
+        measured.target_offset,
+    );
+    ffmpeg::reencode_in_place(path, &filter, format, profile)?;
+
+    meta.loudness_lufs = Some(target_lufs);
+    meta.size_bytes = fs::metadata(path).map(|metadata| metadata.len()).ok();
+    meta.save(store, path)?;
+
+    Ok(())
+}
+
+/// Run loudnorm's measuring pass over `path`.
+fn measure(path: &Path, target_lufs: f64) -> Result<Measured> {
+    let output = ffmpeg::run(
+        Command::new("ffmpeg")
+            .args(["-hide_banner", "-nostdin", "-i"])
+            .arg(path)
+            .args([
+                "-af",
+                &format!("{}:print_format=json", loudnorm(target_lufs)),
+            ])
+            .args(["-f", "null", "-"]),
+    )?;
+
+    parse_measured(&String::from_utf8_lossy(&output.stderr))
+}
+
+fn loudnorm(target_lufs: f64) -> String {
+    format!("loudnorm=I={target_lufs}:TP={TRUE_PEAK}:LRA={LOUDNESS_RANGE}")
+}
+
+/// loudnorm prints its measurements as the last JSON object on stderr.
+fn parse_measured(stderr: &str) -> Result<Measured> {
+    let start = stderr
+        .rfind('{')
+        .ok_or(eyre!("loudnorm printed no measurements"))?;
+    let end = stderr[start..]
+        .find('}')
+        .ok_or(eyre!("loudnorm measurements are cut off"))?;
+
+    Ok(serde_json::from_str(&stderr[start..=start + end])
+        .wrap_err("parsing loudnorm measurements")?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_measured() {
+        let stderr = r#"
+size=N/A time=00:10:00.00 bitrate=N/A speed= 512x
+[Parsed_loudnorm_0 @ 0x55d0c0a7c2c0]
+{
+	"input_i" : "-27.61",
+	"input_tp" : "-4.47",
+	"input_lra" : "18.06",
+	"input_thresh" : "-39.20",
+	"output_i" : "-16.58",
+	"output_tp" : "-1.50",
+	"output_lra" : "14.78",
+	"output_thresh" : "-27.71",
+	"normalization_type" : "dynamic",
+	"target_offset" : "0.58"
+}
+"#;
+
+        let measured = parse_measured(stderr).unwrap();
+        assert_eq!(measured.input_i, "-27.61");
+        assert_eq!(measured.target_offset, "0.58");
+        assert!(parse_measured("no json here").is_err());
+    }
+}
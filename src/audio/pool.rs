@@ -0,0 +1,123 @@
+use std::{
+    future::Future,
+    panic::AssertUnwindSafe,
+    sync::{
+        mpsc::{self, SyncSender, TrySendError},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use tokio::sync::oneshot;
+
+use crate::error::VpodError;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// Fixed set of OS threads that run blocking yt-dlp jobs, fed from a bounded
+/// queue so the async runtime never waits on a download.
+#[derive(Clone)]
+pub(crate) struct DownloadPool {
+    queue: SyncSender<Job>,
+}
+
+impl DownloadPool {
+    pub(crate) fn new(workers: usize, queue_len: usize) -> Self {
+        let (queue, jobs) = mpsc::sync_channel::<Job>(queue_len);
+        let jobs = Arc::new(Mutex::new(jobs));
+
+        for i in 0..workers.max(1) {
+            let jobs = jobs.clone();
+            thread::Builder::new()
+                .name(format!("download-worker-{i}"))
+                .spawn(move || loop {
+                    let job = jobs.lock().unwrap().recv();
+                    match job {
+                        Ok(job) => {
+                            if std::panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                                tracing::error!("download job panicked");
+                            }
+                        }
+                        Err(_) => break,
+                    }
+                })
+                .expect("could not spawn download worker");
+        }
+
+        Self { queue }
+    }
+
+    /// Queue `job` and wait for a worker to finish it.
+    ///
+    /// Fails straight away with [`VpodError::DownloadQueueFull`] rather than
+    /// waiting when every worker is busy and the queue has no room left.
+    pub(crate) async fn run<F, T>(&self, job: F) -> Result<T, VpodError>
+    where
+        F: FnOnce() -> Result<T, VpodError> + Send + 'static,
+        T: Send + 'static,
+    {
+        self.submit(job)?.await
+    }
+
+    /// Queue `job` without waiting for it. The returned future resolves once
+    /// a worker has finished it.
+    pub(crate) fn submit<F, T>(
+        &self,
+        job: F,
+    ) -> Result<impl Future<Output = Result<T, VpodError>> + Send + 'static, VpodError>
+    where
+        F: FnOnce() -> Result<T, VpodError> + Send + 'static,
+        T: Send + 'static,
+    {
+        let (done, result) = oneshot::channel();
+        let job: Job = Box::new(move || {
+            let _ = done.send(job());
+        });
+
+        self.queue.try_send(job).map_err(|e| match e {
+            TrySendError::Full(_) => VpodError::DownloadQueueFull,
+            TrySendError::Disconnected(_) => VpodError::YoutubeDLError,
+        })?;
+
+        Ok(async move { result.await.map_err(|_| VpodError::YoutubeDLError)? })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_full_queue_is_rejected() {
+        let pool = DownloadPool::new(1, 1);
+        let (release, blocked) = mpsc::channel::<()>();
+        let blocked = Arc::new(Mutex::new(blocked));
+
+        // Occupy the only worker, then the only queue slot.
+        let busy = {
+            let pool = pool.clone();
+            let blocked = blocked.clone();
+            tokio::spawn(async move {
+                pool.run(move || {
+                    blocked.lock().unwrap().recv().unwrap();
+                    Ok(())
+                })
+                .await
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let queued = {
+            let pool = pool.clone();
+            tokio::spawn(async move { pool.run(|| Ok(())).await })
+        };
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let rejected = pool.run(|| Ok(())).await;
+        assert!(matches!(rejected, Err(VpodError::DownloadQueueFull)));
+
+        release.send(()).unwrap();
+        assert!(busy.await.unwrap().is_ok());
+        assert!(queued.await.unwrap().is_ok());
+    }
+}
@@ -0,0 +1,46 @@
+use std::{path::Path, process::Command};
+
+use color_eyre::eyre::{eyre, WrapErr};
+
+use crate::error::Result;
+
+/// Length of the audio in `path` in whole seconds, as ffprobe reports it.
+#[tracing::instrument]
+pub(crate) fn duration_secs(path: &Path) -> Option<u32> {
+    let secs: f64 = ffprobe(path, "format=duration")
+        .map_err(|e| tracing::warn!("{e:?}"))
+        .ok()?
+        .parse()
+        .ok()?;
+    Some(secs.round() as u32)
+}
+
+/// Sample rate of the first audio stream in `path`, as ffprobe reports it.
+#[tracing::instrument]
+pub(crate) fn sample_rate(path: &Path) -> Option<u32> {
+    ffprobe(path, "stream=sample_rate")
+        .map_err(|e| tracing::warn!("{e:?}"))
+        .ok()?
+        .parse()
+        .ok()
+}
+
+/// The value of `entries`, e.g. `format=duration`, for `path`, trimmed.
+/// Stream entries are those of the first audio stream.
+fn ffprobe(path: &Path, entries: &str) -> Result<String> {
+    let output = Command::new("ffprobe")
+        .args(["-v", "error"])
+        .args(["-select_streams", "a:0"])
+        .args(["-show_entries", entries])
+        .args(["-of", "default=noprint_wrappers=1:nokey=1"])
+        .arg(path)
+        .output()
+        .wrap_err("running ffprobe")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(eyre!("ffprobe failed: {}", stderr.trim()).into());
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
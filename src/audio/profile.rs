@@ -0,0 +1,260 @@
+use serde::{Deserialize, Serialize};
+use ytd_rs::Arg;
+
+use super::AudioFormat;
+
+/// A named set of yt-dlp options, chosen per feed in the config file.
+///
+/// Every option is typed, so nothing a user writes in the config can reach
+/// yt-dlp as an extra flag.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct DownloadProfile {
+    /// Format selectors tried in order until one matches.
+    pub(crate) formats: Vec<FormatSelector>,
+    /// Highest average audio bitrate in kbps that any selector may pick.
+    /// Also the target bitrate when converting to another codec.
+    pub(crate) max_bitrate: Option<u32>,
+    /// What episodes are delivered as. Changes the enclosure URLs of the feed.
+    pub(crate) codec: AudioFormat,
+    pub(crate) sponsorblock: SponsorBlock,
+    pub(crate) embed_thumbnail: bool,
+    pub(crate) embed_metadata: bool,
+    pub(crate) concurrent_fragments: u8,
+    /// Stream the first download of an episode to its listener while it is
+    /// still being written to the cache. Post-processing is skipped.
+    pub(crate) stream_first_download: bool,
+    /// Tallest video, in pixels, downloaded when episodes are delivered as
+    /// video. `formats` and `max_bitrate` only apply to audio.
+    pub(crate) max_height: u32,
+}
+
+impl Default for DownloadProfile {
+    fn default() -> Self {
+        Self {
+            formats: vec![
+                FormatSelector("bestaudio[protocol^=http][ext=m4a]".to_string()),
+                FormatSelector("bestaudio[ext=m4a]".to_string()),
+                FormatSelector("bestaudio".to_string()),
+            ],
+            max_bitrate: Some(100),
+            codec: AudioFormat::default(),
+            sponsorblock: SponsorBlock::default(),
+            embed_thumbnail: true,
+            embed_metadata: true,
+            concurrent_fragments: 8,
+            stream_first_download: false,
+            max_height: 720,
+        }
+    }
+}
+
+impl DownloadProfile {
+    pub(crate) const MAX_CONCURRENT_FRAGMENTS: u8 = 32;
+    pub(crate) const MIN_HEIGHT: u32 = 144;
+
+    /// The `--format` value: every selector, capped to `max_bitrate`,
+    /// joined into one fallback chain.
+    pub(crate) fn format(&self) -> String {
+        self.format_chain("")
+    }
+
+    /// Like [`DownloadProfile::format`], but only ever picking `ext`, for
+    /// when the download can't be converted afterwards.
+    pub(crate) fn format_with_ext(&self, ext: &str) -> String {
+        self.format_chain(&format!("[ext={ext}]"))
+    }
+
+    /// The `--format` value for video: MP4 streams no taller than
+    /// `max_height` first, anything no taller after that.
+    pub(crate) fn video_format(&self) -> String {
+        let height = format!("[height<={}]", self.max_height);
+        [
+            format!("bv*{height}[ext=mp4]+ba[ext=m4a]"),
+            format!("b{height}[ext=mp4]"),
+            format!("bv*{height}+ba"),
+            format!("b{height}"),
+        ]
+        .join("/")
+    }
+
+    fn format_chain(&self, filter: &str) -> String {
+        let cap = self
+            .max_bitrate
+            .map(|kbps| format!("[abr<={kbps}]"))
+            .unwrap_or_default();
+
+        self.formats
+            .iter()
+            .map(|selector| format!("{}{cap}{filter}", selector.0))
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    /// Arguments to download an episode as `codec`, which need not be the
+    /// profile's own codec when an older enclosure URL is requested.
+    pub(crate) fn args(&self, codec: AudioFormat) -> Vec<Arg> {
+        let mut args = vec![
+            Arg::new("--quiet"),
+            Arg::new_with_arg(
+                "--concurrent-fragments",
+                &self.concurrent_fragments.to_string(),
+            ),
+        ];
+
+        if codec.is_video() {
+            args.push(Arg::new_with_arg("--format", &self.video_format()));
+            args.push(Arg::new_with_arg(
+                "--merge-output-format",
+                codec.ytdlp_format(),
+            ));
+            args.push(Arg::new_with_arg("--remux-video", codec.ytdlp_format()));
+        } else {
+            args.push(Arg::new_with_arg("--format", &self.format()));
+            args.push(Arg::new("--extract-audio"));
+            args.push(Arg::new_with_arg("--audio-format", codec.ytdlp_format()));
+            if let Some(kbps) = self.max_bitrate {
+                args.push(Arg::new_with_arg("--audio-quality", &format!("{kbps}K")));
+            }
+        }
+
+        if self.embed_metadata {
+            args.push(Arg::new("--embed-metadata"));
+        }
+        if self.embed_thumbnail {
+            args.push(Arg::new("--embed-thumbnail"));
+        }
+        args.extend(self.sponsorblock.args());
+
+        args
+    }
+}
+
+/// One yt-dlp format selector, e.g. `bestaudio[ext=m4a]`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub(crate) struct FormatSelector(String);
+
+impl TryFrom<String> for FormatSelector {
+    type Error = String;
+
+    fn try_from(selector: String) -> Result<Self, Self::Error> {
+        let allowed = |c: char| c.is_ascii_alphanumeric() || "[]<>=!^$*~?._+-:,()".contains(c);
+
+        if selector.is_empty() || selector.starts_with('-') || !selector.chars().all(allowed) {
+            Err(format!("invalid format selector '{selector}'"))
+        } else {
+            Ok(Self(selector))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct SponsorBlock {
+    pub(crate) mode: SponsorBlockMode,
+    pub(crate) categories: Vec<SponsorCategory>,
+}
+
+impl Default for SponsorBlock {
+    fn default() -> Self {
+        Self {
+            mode: SponsorBlockMode::Mark,
+            categories: vec![SponsorCategory::Sponsor, SponsorCategory::Selfpromo],
+        }
+    }
+}
+
+impl SponsorBlock {
+    /// Whether yt-dlp is asked to do anything with segments at all.
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.mode != SponsorBlockMode::Off && !self.categories.is_empty()
+    }
+
+    /// Whether episodes come out shorter than the video.
+    pub(crate) fn removes_segments(&self) -> bool {
+        self.mode == SponsorBlockMode::Remove && self.is_enabled()
+    }
+
+    fn args(&self) -> Vec<Arg> {
+        if self.categories.is_empty() {
+            return vec![];
+        }
+
+        let categories = self
+            .categories
+            .iter()
+            .map(SponsorCategory::as_str)
+            .collect::<Vec<_>>()
+            .join(",");
+
+        match self.mode {
+            SponsorBlockMode::Off => vec![],
+            SponsorBlockMode::Mark => vec![Arg::new_with_arg("--sponsorblock-mark", &categories)],
+            SponsorBlockMode::Remove => {
+                vec![Arg::new_with_arg("--sponsorblock-remove", &categories)]
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum SponsorBlockMode {
+    Off,
+    /// Add a chapter for every segment.
+    Mark,
+    /// Cut every segment out of the audio.
+    Remove,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum SponsorCategory {
+    Sponsor,
+    Selfpromo,
+    Interaction,
+    Intro,
+    Outro,
+    Preview,
+    Hook,
+    Filler,
+    MusicOfftopic,
+    PoiHighlight,
+    Chapter,
+}
+
+impl SponsorCategory {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Self::Sponsor => "sponsor",
+            Self::Selfpromo => "selfpromo",
+            Self::Interaction => "interaction",
+            Self::Intro => "intro",
+            Self::Outro => "outro",
+            Self::Preview => "preview",
+            Self::Hook => "hook",
+            Self::Filler => "filler",
+            Self::MusicOfftopic => "music_offtopic",
+            Self::PoiHighlight => "poi_highlight",
+            Self::Chapter => "chapter",
+        }
+    }
+
+    /// How a segment of this category is named in chapter lists.
+    pub(crate) fn title(&self) -> &'static str {
+        match self {
+            Self::Sponsor => "Sponsor",
+            Self::Selfpromo => "Self-promotion",
+            Self::Interaction => "Interaction reminder",
+            Self::Intro => "Intro",
+            Self::Outro => "Outro",
+            Self::Preview => "Preview",
+            Self::Hook => "Hook",
+            Self::Filler => "Filler",
+            Self::MusicOfftopic => "Non-music section",
+            Self::PoiHighlight => "Highlight",
+            Self::Chapter => "Chapter",
+        }
+    }
+}
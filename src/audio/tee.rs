@@ -0,0 +1,143 @@
+use std::{
+    fs::{self, File},
+    io::{Read, Write},
+    path::Path,
+};
+
+use axum::body::{Body, Bytes};
+use futures::StreamExt;
+use tokio::sync::mpsc;
+
+use super::{downloader::RunError, failure, DownloadProfile, Downloader, EpisodeMeta, STAGING_DIR};
+use crate::error::VpodError;
+use crate::feed::Store;
+
+/// Bytes read from the download at a time.
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Chunks buffered for the first listener before the download waits on them.
+pub(crate) const CHANNEL_CHUNKS: usize = 256;
+
+pub(crate) type Chunk = Result<Bytes, std::io::Error>;
+
+/// Download `ep_id` to stdout and copy every chunk both to `path` and to the
+/// first listener at the same time.
+///
+/// yt-dlp can't post-process a download written to stdout, so metadata,
+/// thumbnails and SponsorBlock are left out in this mode, which is why it
+/// can't be combined with removing segments. The download runs to completion
+/// even if the listener goes away, so the file still lands in the cache.
+#[tracing::instrument(skip(store, downloader, profile, chunks))]
+pub(crate) fn tee(
+    store: &Store,
+    downloader: &dyn Downloader,
+    ep_id: &str,
+    path: &Path,
+    profile: &DownloadProfile,
+    chunks: mpsc::Sender<Chunk>,
+) -> Result<(), VpodError> {
+    let result = tee_to_file(store, downloader, ep_id, path, profile, &chunks);
+    if let Err(e) = &result {
+        // Cut the response short so the listener doesn't keep a truncated
+        // file, or fail it outright if nothing was sent yet.
+        let _ = chunks.blocking_send(Err(std::io::Error::other(e.clone())));
+    }
+    result
+}
+
+/// The response body for the first listener, once the download has produced
+/// something. A download that fails before that fails with its own error,
+/// one that ends without any output gives `None`.
+pub(crate) async fn first_listener_body(
+    mut listener: mpsc::Receiver<Chunk>,
+) -> Result<Option<Body>, VpodError> {
+    let first = match listener.recv().await {
+        Some(Ok(chunk)) => chunk,
+        Some(Err(e)) => {
+            return Err(e
+                .get_ref()
+                .and_then(|e| e.downcast_ref::<VpodError>())
+                .cloned()
+                .unwrap_or(VpodError::YoutubeDLError))
+        }
+        None => return Ok(None),
+    };
+
+    let rest = futures::stream::unfold(listener, |mut listener| async move {
+        listener.recv().await.map(|chunk| (chunk, listener))
+    });
+    let body = futures::stream::once(async { Ok(first) }).chain(rest);
+    Ok(Some(Body::from_stream(body)))
+}
+
+fn tee_to_file(
+    store: &Store,
+    downloader: &dyn Downloader,
+    ep_id: &str,
+    path: &Path,
+    profile: &DownloadProfile,
+    chunks: &mpsc::Sender<Chunk>,
+) -> Result<(), VpodError> {
+    let staging_dir = path
+        .parent()
+        .ok_or(VpodError::YoutubeDLError)?
+        .join(STAGING_DIR);
+    let staged = staging_dir.join(path.file_name().ok_or(VpodError::YoutubeDLError)?);
+
+    let io_error = |e: std::io::Error| {
+        tracing::error!("could not stream download: {e}");
+        VpodError::YoutubeDLError
+    };
+
+    fs::create_dir_all(&staging_dir).map_err(io_error)?;
+    let mut file = File::create(&staged).map_err(io_error)?;
+
+    let streamed = downloader
+        .stream(ep_id, profile)
+        .map_err(|e| failed(&staged, e))?;
+    let mut output = streamed.output;
+
+    let mut listening = true;
+    let mut buf = vec![0; CHUNK_SIZE];
+    loop {
+        let read = output.read(&mut buf).map_err(io_error)?;
+        if read == 0 {
+            break;
+        }
+        file.write_all(&buf[..read]).map_err(io_error)?;
+        if listening
+            && chunks
+                .blocking_send(Ok(Bytes::copy_from_slice(&buf[..read])))
+                .is_err()
+        {
+            tracing::debug!("listener went away, finishing download for the cache");
+            listening = false;
+        }
+    }
+
+    (streamed.finish)().map_err(|e| failed(&staged, e))?;
+
+    file.sync_all().map_err(io_error)?;
+    fs::rename(&staged, path).map_err(io_error)?;
+
+    // No SponsorBlock segments were applied, so there is nothing to recheck.
+    if let Err(e) = EpisodeMeta::measure(path).save(store, path) {
+        tracing::warn!("could not save episode metadata: {e:?}");
+    }
+
+    Ok(())
+}
+
+/// Drop what was `staged` of a download that failed, and say why it did.
+fn failed(staged: &Path, e: RunError) -> VpodError {
+    let _ = fs::remove_file(staged);
+    match e {
+        RunError::Failed(stderr) => match failure::classify(&stderr) {
+            failure::Failure::Permanent(e) => e,
+            failure::Failure::Transient => VpodError::YoutubeDLError,
+        },
+        RunError::Unavailable(e) => {
+            tracing::error!("could not stream download: {e}");
+            VpodError::YoutubeDLError
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use std::path::Path;
+
+use super::{ffmpeg, staged, AudioFormat, DownloadProfile, EpisodeMeta};
+use crate::error::Result;
+use crate::feed::Store;
+
+/// Speeds a feed may ask for. ffmpeg's `atempo` takes at most 2x at a time,
+/// faster tempos are chained.
+pub(crate) const TEMPOS: std::ops::RangeInclusive<f64> = 0.5..=4.0;
+
+/// What an episode file name asks for, e.g. `abc.1.5x.m4a` is video `abc`
+/// sped up 1.5 times, as AAC.
+#[derive(Debug, PartialEq)]
+pub(crate) struct EpisodeFile<'a> {
+    pub(crate) ep_id: &'a str,
+    pub(crate) tempo: Option<f64>,
+    pub(crate) format: AudioFormat,
+}
+
+impl<'a> EpisodeFile<'a> {
+    /// Split `file_name` into its parts. Only the canonical spelling of a
+    /// tempo is accepted, so one variant can't be cached under many names.
+    pub(crate) fn parse(file_name: &'a str) -> Option<Self> {
+        let format = AudioFormat::from_file_name(file_name)?;
+        let stem = file_name
+            .strip_suffix(format.extension())?
+            .strip_suffix('.')?;
+
+        Self::parse_stem(stem, format)
+    }
+
+    /// Like [`EpisodeFile::parse`], for a name with its extension cut off
+    /// already, e.g. `abc.1.5x`. The ID must look like a YouTube video ID,
+    /// so the name can't reach outside the feed's directory.
+    pub(crate) fn parse_stem(stem: &'a str, format: AudioFormat) -> Option<Self> {
+        let (ep_id, tempo) = match stem.split_once('.') {
+            None => (stem, None),
+            Some((ep_id, tempo)) => {
+                let tempo = tempo.strip_suffix('x')?;
+                let parsed: f64 = tempo.parse().ok()?;
+                if parsed.to_string() != tempo {
+                    return None;
+                }
+                (ep_id, Some(parsed))
+            }
+        };
+        if !is_video_id(ep_id) {
+            return None;
+        }
+
+        Some(Self {
+            ep_id,
+            tempo,
+            format,
+        })
+    }
+
+    /// The file name without its extension, e.g. `abc.1.5x`.
+    pub(crate) fn stem(&self) -> String {
+        match self.tempo {
+            Some(tempo) => format!("{}.{tempo}x", self.ep_id),
+            None => self.ep_id.to_string(),
+        }
+    }
+
+    pub(crate) fn file_name(&self) -> String {
+        format!("{}.{}", self.stem(), self.format.extension())
+    }
+
+    /// The file this one is made from, the episode at its normal speed.
+    pub(crate) fn base(&self) -> Self {
+        Self {
+            tempo: None,
+            ..*self
+        }
+    }
+}
+
+/// Whether `id` looks like a YouTube video ID, e.g. `dQw4w9WgXcQ`.
+pub(crate) fn is_video_id(id: &str) -> bool {
+    id.len() == 11
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Make the `tempo` variant of the episode at `base` at `path`, keeping the
+/// pitch as it is.
+#[tracing::instrument(skip(store, profile))]
+pub(crate) fn make_variant(
+    store: &Store,
+    base: &Path,
+    path: &Path,
+    tempo: f64,
+    format: AudioFormat,
+    profile: &DownloadProfile,
+) -> Result<()> {
+    let staged = staged(path)?;
+    ffmpeg::reencode(base, &staged, &atempo(tempo), format, profile)?;
+    std::fs::rename(&staged, path)?;
+
+    if let Err(e) = EpisodeMeta::measure(path).save(store, path) {
+        tracing::warn!("could not save episode metadata: {e:?}");
+    }
+
+    Ok(())
+}
+
+/// An `atempo` filter chain for `tempo`, as no single one may go above 2x.
+fn atempo(mut tempo: f64) -> String {
+    let mut filters = vec![];
+    while tempo > 2.0 {
+        filters.push("atempo=2".to_string());
+        tempo /= 2.0;
+    }
+    filters.push(format!("atempo={tempo}"));
+    filters.join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_file_names() {
+        let file = EpisodeFile::parse("dQw4w9WgXcQ.1.5x.m4a").unwrap();
+        assert_eq!(file.ep_id, "dQw4w9WgXcQ");
+        assert_eq!(file.tempo, Some(1.5));
+        assert_eq!(file.format, AudioFormat::Aac);
+        assert_eq!(file.file_name(), "dQw4w9WgXcQ.1.5x.m4a");
+        assert_eq!(file.base().file_name(), "dQw4w9WgXcQ.m4a");
+
+        let file = EpisodeFile::parse("dQw4w9WgXcQ.opus").unwrap();
+        assert_eq!(file.tempo, None);
+        assert_eq!(file.format, AudioFormat::Opus);
+
+        assert_eq!(EpisodeFile::parse("dQw4w9WgXcQ.1.50x.m4a"), None);
+        assert_eq!(EpisodeFile::parse("dQw4w9WgXcQ.fast.m4a"), None);
+        assert_eq!(EpisodeFile::parse("dQw4w9WgXcQ.wav"), None);
+        assert_eq!(EpisodeFile::parse("abc.m4a"), None);
+        assert_eq!(EpisodeFile::parse("/tmp/x.m4a"), None);
+        assert_eq!(EpisodeFile::parse("..%2Fxxxxxxxx.m4a"), None);
+    }
+
+    #[test]
+    fn test_fast_tempos_are_chained() {
+        assert_eq!(atempo(1.5), "atempo=1.5");
+        assert_eq!(atempo(3.0), "atempo=2,atempo=1.5");
+    }
+}
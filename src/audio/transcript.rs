@@ -0,0 +1,421 @@
+use std::{
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use tower::ServiceExt;
+
+use super::{fetch_segments, retrying, staged, AudioFormat, Downloader, EpisodeFile, SponsorBlock};
+use crate::error::{Result, VpodError};
+use crate::sponsorblock::{self, Segment};
+use crate::state::AppState;
+
+/// How long a video found without captions isn't asked for them again.
+/// Uploaders add captions later, and automatic ones take a while to appear.
+const UNAVAILABLE_FOR: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Formats transcripts are served in, by file extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TranscriptFormat {
+    Vtt,
+    Srt,
+}
+
+impl TranscriptFormat {
+    pub(crate) const ALL: [TranscriptFormat; 2] = [Self::Vtt, Self::Srt];
+
+    pub(crate) fn extension(&self) -> &'static str {
+        match self {
+            Self::Vtt => "vtt",
+            Self::Srt => "srt",
+        }
+    }
+
+    pub(crate) fn mime_type(&self) -> &'static str {
+        match self {
+            Self::Vtt => "text/vtt",
+            Self::Srt => "application/x-subrip",
+        }
+    }
+
+    /// The format a transcript file name asks for, and the file name
+    /// without its extension.
+    pub(crate) fn split_file_name(file_name: &str) -> Option<(&str, Self)> {
+        Self::ALL.into_iter().find_map(|format| {
+            let stem = file_name
+                .strip_suffix(format.extension())?
+                .strip_suffix('.')?;
+            Some((stem, format))
+        })
+    }
+}
+
+/// One caption, with times in seconds.
+#[derive(Debug, Clone, PartialEq)]
+struct Cue {
+    start: f64,
+    end: f64,
+    text: String,
+}
+
+/// Serve the transcript of the episode `stem` of `feed_id` as `format`,
+/// fetching its captions first if they haven't been yet.
+///
+/// Both formats are written together, the WebVTT file last, so it existing
+/// means the SRT file does too.
+#[tracing::instrument(skip(state, request))]
+pub(crate) async fn serve_transcript(
+    state: &AppState,
+    feed_id: &str,
+    stem: &str,
+    format: TranscriptFormat,
+    request: axum::extract::Request,
+) -> Result<Response> {
+    let settings = state.config.feed(feed_id);
+    let Some(language) = settings.transcripts().map(str::to_owned) else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    // Paths are only ever built from the parsed stem, which can't leave the
+    // feed's directory.
+    let Some(file) = EpisodeFile::parse_stem(stem, AudioFormat::default()) else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    if file.stem() != stem
+        || file
+            .tempo
+            .is_some_and(|tempo| !settings.offers_tempo(tempo))
+    {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    }
+    let stem = file.stem();
+
+    let feed_dir = state.feed_dir(feed_id);
+    if is_unavailable(&feed_dir, file.ep_id, &language) {
+        return Err(VpodError::TranscriptUnavailable.into());
+    }
+    let vtt = transcript_path(&feed_dir, &stem, TranscriptFormat::Vtt);
+    let ep_id = file.ep_id.to_owned();
+    let tempo = file.tempo;
+    let profile = state.config.profile(feed_id);
+
+    state
+        .downloads
+        .fetch(&vtt, || {
+            let pool = state.pool.clone();
+            let api = state.sponsorblock_api.clone();
+            let downloader = state.downloader.clone();
+            let vtt = vtt.clone();
+            async move {
+                let segments = fetch_segments(&api, &ep_id, &profile.sponsorblock)
+                    .await
+                    .unwrap_or_default();
+                pool.run(move || {
+                    fetch(
+                        &*downloader,
+                        &ep_id,
+                        &vtt,
+                        &language,
+                        &segments,
+                        &profile.sponsorblock,
+                        tempo,
+                    )
+                })
+                .await
+            }
+        })
+        .await?;
+
+    let service = tower_http::services::ServeFile::new(transcript_path(&feed_dir, &stem, format));
+    Ok(service.oneshot(request).await.into_response())
+}
+
+fn transcript_path(feed_dir: &Path, stem: &str, format: TranscriptFormat) -> PathBuf {
+    feed_dir.join(format!("{stem}.{}", format.extension()))
+}
+
+/// Left in `feed_dir` when `ep_id` turned out to have no `language` captions.
+fn unavailable_marker(feed_dir: &Path, ep_id: &str, language: &str) -> PathBuf {
+    feed_dir.join(format!("{ep_id}.{language}.no-captions"))
+}
+
+/// Whether `ep_id` was found to have no `language` captions recently.
+pub(crate) fn is_unavailable(feed_dir: &Path, ep_id: &str, language: &str) -> bool {
+    fs::metadata(unavailable_marker(feed_dir, ep_id, language))
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_some_and(|age| age < UNAVAILABLE_FOR)
+}
+
+/// Fetch `language` captions for `ep_id`, preferring ones the uploader wrote
+/// over automatic ones, and write them as `vtt` and its SRT sibling.
+///
+/// Captions are cut and sped up the same way as the audio they go with. A
+/// video without any is marked as such, see [`is_unavailable`].
+#[tracing::instrument(skip(downloader, segments, sponsorblock))]
+pub(crate) fn fetch(
+    downloader: &dyn Downloader,
+    ep_id: &str,
+    vtt: &Path,
+    language: &str,
+    segments: &[Segment],
+    sponsorblock: &SponsorBlock,
+    tempo: Option<f64>,
+) -> Result<(), VpodError> {
+    let io_error = |e: std::io::Error| {
+        tracing::error!("could not write transcript: {e}");
+        VpodError::YoutubeDLError
+    };
+    let staged_vtt = staged(vtt).map_err(|e| {
+        tracing::error!("could not prepare staging directory: {e:?}");
+        VpodError::YoutubeDLError
+    })?;
+    let feed_dir = vtt.parent().ok_or(VpodError::YoutubeDLError)?;
+
+    // yt-dlp names the captions after the video, so the transcripts of one
+    // episode at different speeds are each fetched into a directory of their
+    // own.
+    let job_dir = staged_vtt.with_extension("captions");
+    fs::create_dir_all(&job_dir).map_err(io_error)?;
+    let fetched = retrying(|| downloader.subtitles(ep_id, &job_dir, language))
+        .map(|()| fs::read_to_string(job_dir.join(format!("{ep_id}.{language}.vtt"))).ok());
+    let _ = fs::remove_dir_all(&job_dir);
+
+    // yt-dlp succeeds without writing anything when there are no captions.
+    let Some(text) = fetched? else {
+        if let Err(e) = fs::write(unavailable_marker(feed_dir, ep_id, language), "") {
+            tracing::warn!("could not mark captions as unavailable: {e}");
+        }
+        return Err(VpodError::TranscriptUnavailable);
+    };
+
+    let mut cues = parse_vtt(&text);
+    if sponsorblock.removes_segments() {
+        cues = cut(cues, &sponsorblock::merged(segments));
+    }
+    if let Some(tempo) = tempo {
+        for cue in &mut cues {
+            cue.start /= tempo;
+            cue.end /= tempo;
+        }
+    }
+
+    let srt = vtt.with_extension(TranscriptFormat::Srt.extension());
+    fs::write(&staged_vtt, to_srt(&cues)).map_err(io_error)?;
+    fs::rename(&staged_vtt, srt).map_err(io_error)?;
+    fs::write(&staged_vtt, to_vtt(&cues)).map_err(io_error)?;
+    fs::rename(&staged_vtt, vtt).map_err(io_error)?;
+
+    Ok(())
+}
+
+/// Remove every transcript of `ep_id` in `feed_dir`, at any speed, so they
+/// are fetched again.
+pub(crate) fn remove(feed_dir: &Path, ep_id: &str) {
+    let Ok(entries) = fs::read_dir(feed_dir) else {
+        return;
+    };
+
+    for path in entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+    {
+        let is_transcript = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(TranscriptFormat::split_file_name)
+            .and_then(|(stem, _)| EpisodeFile::parse_stem(stem, AudioFormat::default()))
+            .is_some_and(|file| file.ep_id == ep_id);
+        if is_transcript {
+            tracing::debug!("removing stale transcript {}", path.display());
+            let _ = fs::remove_file(&path);
+        }
+    }
+}
+
+/// Cues of a WebVTT file, with markup stripped.
+///
+/// YouTube's automatic captions repeat the previous line at the top of every
+/// cue so it scrolls, those repeats are dropped.
+fn parse_vtt(text: &str) -> Vec<Cue> {
+    let mut cues: Vec<Cue> = vec![];
+    let mut last_line = String::new();
+
+    for block in text.replace("\r\n", "\n").split("\n\n") {
+        let mut lines = block.lines().skip_while(|line| !line.contains("-->"));
+        let Some((start, end)) = lines.next().and_then(parse_timing) else {
+            continue;
+        };
+
+        let mut new_lines = vec![];
+        for line in lines {
+            let line = strip_tags(line);
+            let line = line.trim();
+            if !line.is_empty() && line != last_line {
+                new_lines.push(line.to_string());
+                last_line = line.to_string();
+            }
+        }
+        if new_lines.is_empty() {
+            continue;
+        }
+
+        cues.push(Cue {
+            start,
+            end,
+            text: new_lines.join("\n"),
+        });
+    }
+
+    cues
+}
+
+/// Start and end of a timing line like `00:00:01.000 --> 00:00:04.000
+/// align:start position:0%`.
+fn parse_timing(line: &str) -> Option<(f64, f64)> {
+    let (start, rest) = line.split_once("-->")?;
+    let end = rest.split_whitespace().next()?;
+    Some((parse_time(start.trim())?, parse_time(end)?))
+}
+
+/// Seconds in `hh:mm:ss.mmm` or `mm:ss.mmm`.
+fn parse_time(time: &str) -> Option<f64> {
+    let (clock, millis) = time.split_once('.')?;
+    let millis: f64 = millis.parse().ok()?;
+    let secs = clock.split(':').try_fold(0.0, |total, part| {
+        part.parse::<f64>().ok().map(|part| total * 60.0 + part)
+    })?;
+    Some((secs * 1000.0 + millis) / 1000.0)
+}
+
+fn strip_tags(line: &str) -> String {
+    let mut stripped = String::with_capacity(line.len());
+    let mut in_tag = false;
+    for c in line.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            c if !in_tag => stripped.push(c),
+            _ => (),
+        }
+    }
+    stripped
+}
+
+/// Drop the cues that fall in `removed` and move the rest up to match.
+fn cut(cues: Vec<Cue>, removed: &[(f64, f64)]) -> Vec<Cue> {
+    cues.into_iter()
+        .map(|cue| Cue {
+            start: sponsorblock::cut_time(removed, cue.start),
+            end: sponsorblock::cut_time(removed, cue.end),
+            ..cue
+        })
+        .filter(|cue| cue.end > cue.start)
+        .collect()
+}
+
+fn to_vtt(cues: &[Cue]) -> String {
+    let mut vtt = String::from("WEBVTT\n");
+    for cue in cues {
+        let _ = write!(
+            vtt,
+            "\n{} --> {}\n{}\n",
+            timestamp(cue.start, '.'),
+            timestamp(cue.end, '.'),
+            cue.text
+        );
+    }
+    vtt
+}
+
+fn to_srt(cues: &[Cue]) -> String {
+    let mut srt = String::new();
+    for (i, cue) in cues.iter().enumerate() {
+        let _ = write!(
+            srt,
+            "{}\n{} --> {}\n{}\n\n",
+            i + 1,
+            timestamp(cue.start, ','),
+            timestamp(cue.end, ','),
+            cue.text
+        );
+    }
+    srt
+}
+
+/// `secs` as `hh:mm:ss.mmm`, with `separator` before the milliseconds.
+fn timestamp(secs: f64, separator: char) -> String {
+    let millis = (secs * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}{separator}{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AUTO_CAPTIONS: &str = "WEBVTT\n\
+        Kind: captions\n\
+        Language: en\n\
+        \n\
+        00:00:00.320 --> 00:00:02.950 align:start position:0%\n\
+        \x20\n\
+        hello<00:00:00.640><c> everyone</c>\n\
+        \n\
+        00:00:02.950 --> 00:00:02.960 align:start position:0%\n\
+        hello everyone\n\
+        \x20\n\
+        \n\
+        00:00:02.960 --> 00:01:05.500 align:start position:0%\n\
+        hello everyone\n\
+        welcome<00:00:03.200><c> back</c>\n";
+
+    #[test]
+    fn test_auto_captions_are_deduplicated() {
+        let cues = parse_vtt(AUTO_CAPTIONS);
+
+        assert_eq!(
+            cues,
+            [
+                Cue {
+                    start: 0.32,
+                    end: 2.95,
+                    text: "hello everyone".to_string()
+                },
+                Cue {
+                    start: 2.96,
+                    end: 65.5,
+                    text: "welcome back".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_cues_are_written_as_vtt_and_srt() {
+        let cues = cut(parse_vtt(AUTO_CAPTIONS), &[(1.0, 2.0)]);
+
+        assert_eq!(
+            to_vtt(&cues),
+            "WEBVTT\n\
+             \n00:00:00.320 --> 00:00:01.950\nhello everyone\n\
+             \n00:00:01.960 --> 00:01:04.500\nwelcome back\n"
+        );
+        assert_eq!(
+            to_srt(&cues),
+            "1\n00:00:00,320 --> 00:00:01,950\nhello everyone\n\n\
+             2\n00:00:01,960 --> 00:01:04,500\nwelcome back\n\n"
+        );
+    }
+}
@@ -0,0 +1,102 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use super::{ffmpeg, AudioFormat, DownloadProfile, EpisodeMeta};
+use crate::error::Result;
+use crate::feed::Store;
+
+/// Pause, in seconds, left where silence was cut so sentences don't run into
+/// each other.
+const KEPT_SILENCE_SECS: f64 = 0.5;
+
+/// How a feed wants long pauses taken out of its episodes.
+///
+/// ```toml
+/// trim_silence = { threshold_db = -45, min_secs = 1.5 }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct SilenceTrim {
+    /// Anything quieter than this, in dB, counts as silence.
+    #[serde(default = "SilenceTrim::default_threshold_db")]
+    pub(crate) threshold_db: f64,
+    /// Only silences longer than this, in seconds, are cut.
+    #[serde(default = "SilenceTrim::default_min_secs")]
+    pub(crate) min_secs: f64,
+}
+
+impl SilenceTrim {
+    fn default_threshold_db() -> f64 {
+        -50.0
+    }
+
+    fn default_min_secs() -> f64 {
+        1.0
+    }
+
+    fn filter(&self) -> String {
+        format!(
+            "silenceremove=start_periods=1:start_threshold={threshold}dB:\
+             stop_periods=-1:stop_duration={min}:stop_threshold={threshold}dB:\
+             stop_silence={KEPT_SILENCE_SECS}",
+            threshold = self.threshold_db,
+            min = self.min_secs,
+        )
+    }
+}
+
+/// Cut the silences `trim` describes out of the episode at `path`, unless
+/// its metadata says that was done already.
+///
+/// The file is replaced atomically and its size and duration measured again.
+#[tracing::instrument(skip(store, profile))]
+pub(crate) fn trim_silence_once(
+    store: &Store,
+    path: &Path,
+    trim: &SilenceTrim,
+    format: AudioFormat,
+    profile: &DownloadProfile,
+) -> Result<()> {
+    let mut meta = EpisodeMeta::load(store, path).unwrap_or_default();
+    if meta.silence_trimmed.as_ref() == Some(trim) {
+        tracing::debug!("silence already trimmed");
+        return Ok(());
+    }
+
+    ffmpeg::reencode_in_place(path, &trim.filter(), format, profile)?;
+
+    let measured = EpisodeMeta::measure(path);
+    tracing::debug!(
+        before = meta.duration_secs,
+        after = measured.duration_secs,
+        "trimmed silence"
+    );
+    meta.silence_trimmed = Some(trim.clone());
+    meta.size_bytes = measured.size_bytes;
+    meta.duration_secs = measured.duration_secs;
+    meta.save(store, path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults_fill_in_missing_fields() {
+        let trim: SilenceTrim = toml::from_str("threshold_db = -40").unwrap();
+
+        assert_eq!(
+            trim,
+            SilenceTrim {
+                threshold_db: -40.0,
+                min_secs: 1.0
+            }
+        );
+        assert!(trim
+            .filter()
+            .contains("stop_duration=1:stop_threshold=-40dB"));
+    }
+}
@@ -3,13 +3,29 @@
 
 use clap::Parser;
 use std::net::{IpAddr, Ipv6Addr};
+use std::path::PathBuf;
 use url::Url;
 
+use crate::audio::DownloaderKind;
+use crate::feed::YOUTUBE_URL;
+
 mod instrumentation;
 mod logger;
 
 const DEFAULT_HOST: IpAddr = IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 0));
 const DEFAULT_PORT: u16 = 8080;
+const DEFAULT_DOWNLOAD_WORKERS: usize = 2;
+const DEFAULT_DOWNLOAD_QUEUE: usize = 16;
+const DEFAULT_CACHE_MAX_BYTES: u64 = 10_000_000_000;
+const DEFAULT_CACHE_MIN_FREE_BYTES: u64 = 1_000_000_000;
+const DEFAULT_CACHE_KEEP_NEWEST: usize = 1;
+const DEFAULT_CACHE_SWEEP_INTERVAL: u64 = 600;
+const DEFAULT_DATA_DIR: &str = ".";
+const DEFAULT_HTTP_TIMEOUT: u64 = 15;
+const DEFAULT_YOUTUBE_RATE_LIMIT: f64 = 2.0;
+const DEFAULT_SPONSORBLOCK_RATE_LIMIT: f64 = 5.0;
+const DEFAULT_REFRESH_INTERVAL: u64 = 3600;
+const DEFAULT_FEED_MAX_AGE: u64 = 900;
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -23,6 +39,70 @@ pub(crate) struct Cli {
     #[clap(long, env = "EPISODE_URL")]
     pub(crate) episode_url: Url,
 
+    /// Where YouTube's pages and feeds are fetched from, e.g. a mock serving recorded fixtures
+    #[clap(long, env = "YOUTUBE_URL", default_value = YOUTUBE_URL)]
+    pub(crate) youtube_url: Url,
+
+    /// Seconds a request to YouTube or SponsorBlock may take before it is given up on
+    #[clap(long, env = "HTTP_TIMEOUT", default_value_t = DEFAULT_HTTP_TIMEOUT)]
+    pub(crate) http_timeout: u64,
+
+    /// Most requests a second made to YouTube's pages and feeds, unlimited if 0
+    #[clap(long, env = "YOUTUBE_RATE_LIMIT", default_value_t = DEFAULT_YOUTUBE_RATE_LIMIT)]
+    pub(crate) youtube_rate_limit: f64,
+
+    /// Most requests a second made to the SponsorBlock API, unlimited if 0
+    #[clap(long, env = "SPONSORBLOCK_RATE_LIMIT", default_value_t = DEFAULT_SPONSORBLOCK_RATE_LIMIT)]
+    pub(crate) sponsorblock_rate_limit: f64,
+
+    /// Seconds between background refreshes of each feed, give or take a tenth
+    #[clap(long, env = "REFRESH_INTERVAL", default_value_t = DEFAULT_REFRESH_INTERVAL)]
+    pub(crate) refresh_interval: u64,
+
+    /// Seconds podcast apps may keep using a feed before asking for it again
+    #[clap(long, env = "FEED_MAX_AGE", default_value_t = DEFAULT_FEED_MAX_AGE)]
+    pub(crate) feed_max_age: u64,
+
+    /// TOML file with download profiles and per-feed settings
+    #[clap(long, env = "CONFIG")]
+    pub(crate) config: Option<PathBuf>,
+
+    /// Number of yt-dlp downloads that may run at the same time
+    #[clap(long, env = "DOWNLOAD_WORKERS", default_value_t = DEFAULT_DOWNLOAD_WORKERS)]
+    pub(crate) download_workers: usize,
+
+    /// Number of downloads that may wait for a free worker before new ones are refused
+    #[clap(long, env = "DOWNLOAD_QUEUE", default_value_t = DEFAULT_DOWNLOAD_QUEUE)]
+    pub(crate) download_queue: usize,
+
+    /// Total size in bytes that downloaded episodes may take up across all feeds
+    #[clap(long, env = "CACHE_MAX_BYTES", default_value_t = DEFAULT_CACHE_MAX_BYTES)]
+    pub(crate) cache_max_bytes: u64,
+
+    /// Keep evicting episodes while the disk has less than this many bytes free
+    #[clap(long, env = "CACHE_MIN_FREE_BYTES", default_value_t = DEFAULT_CACHE_MIN_FREE_BYTES)]
+    pub(crate) cache_min_free_bytes: u64,
+
+    /// Number of most recently downloaded episodes per feed that are never evicted
+    #[clap(long, env = "CACHE_KEEP_NEWEST", default_value_t = DEFAULT_CACHE_KEEP_NEWEST)]
+    pub(crate) cache_keep_newest: usize,
+
+    /// Seconds between background sweeps of the episode cache
+    #[clap(long, env = "CACHE_SWEEP_INTERVAL", default_value_t = DEFAULT_CACHE_SWEEP_INTERVAL)]
+    pub(crate) cache_sweep_interval: u64,
+
+    /// Directory feeds and their episodes are stored in, one subdirectory per feed
+    #[clap(long, env = "DATA_DIR", default_value = DEFAULT_DATA_DIR)]
+    pub(crate) data_dir: PathBuf,
+
+    /// SQLite database feeds are kept in, `vpod.db` in the data directory unless set
+    #[clap(long, env = "DATABASE")]
+    pub(crate) database: Option<PathBuf>,
+
+    /// What episodes are downloaded with; `fake` serves fixture audio without network access
+    #[clap(long, env = "DOWNLOADER", value_enum, default_value_t = DownloaderKind::YtDlp)]
+    pub(crate) downloader: DownloaderKind,
+
     #[clap(flatten)]
     pub(crate) instrumentation: instrumentation::Instrumentation,
 }
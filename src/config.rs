@@ -0,0 +1,439 @@
+use std::{collections::HashMap, path::Path};
+
+use color_eyre::eyre::{eyre, WrapErr};
+use serde::{Deserialize, Serialize};
+
+use crate::audio::{self, AudioFormat, DownloadProfile, SilenceTrim, SponsorBlock};
+
+/// Profile used by feeds that don't name one. Can be overridden in the config.
+const DEFAULT_PROFILE: &str = "default";
+
+const DEFAULT_SPONSORBLOCK_RECHECK_DAYS: u64 = 3;
+
+/// Loudness targets ffmpeg's loudnorm filter accepts.
+const LOUDNESS_TARGET_LUFS: std::ops::RangeInclusive<f64> = -70.0..=-5.0;
+
+/// Settings read from the TOML file passed with `--config`.
+///
+/// ```toml
+/// [profiles.small]
+/// formats = ["bestaudio[ext=m4a]"]
+/// max_bitrate = 64
+///
+/// [feeds.UCNmv1Cmjm3Hk8Vc9kIgv0AQ]
+/// profile = "small"
+/// loudness_target_lufs = -16
+/// trim_silence = { threshold_db = -50 }
+/// tempos = [1.5, 2]
+/// transcript_language = "en"
+///
+/// [feeds.UCsBjURrPoezykLs9EqgamOA]
+/// video = true
+/// ```
+///
+/// The file is the only place feed settings are read from. At startup the
+/// `[feeds.*]` sections replace every feed's settings in the store, so a
+/// section that is taken out stops applying.
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Config {
+    profiles: HashMap<String, DownloadProfile>,
+    feeds: HashMap<String, FeedSettings>,
+    /// Settings for feeds that have no section of their own.
+    #[serde(skip)]
+    default_feed: FeedSettings,
+}
+
+/// Per-feed settings, keyed by channel or playlist ID.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct FeedSettings {
+    /// Name of the download profile used for this feed's episodes.
+    pub(crate) profile: Option<String>,
+    /// Overrides the profile's SponsorBlock settings for this feed only.
+    pub(crate) sponsorblock: Option<SponsorBlock>,
+    /// Hours a new video is held out of the feed, so SponsorBlock users have
+    /// time to submit segments before anyone downloads it.
+    pub(crate) embargo_hours: u64,
+    /// Days after a video is published during which its SponsorBlock
+    /// segments are checked again, and its cached audio redone if they changed.
+    pub(crate) sponsorblock_recheck_days: u64,
+    /// Integrated loudness, in LUFS, episodes are normalized to after
+    /// downloading. Left as they come from YouTube if unset.
+    pub(crate) loudness_target_lufs: Option<f64>,
+    /// Cut long pauses out of episodes after downloading. Chapters and
+    /// transcripts aren't offered then, as their times would be off.
+    pub(crate) trim_silence: Option<SilenceTrim>,
+    /// Sped-up variants offered besides the normal speed, e.g. `[1.5, 2]`.
+    /// A variant's feed is asked for with `?tempo=1.5`.
+    pub(crate) tempos: Vec<f64>,
+    /// Language of the captions fetched as episode transcripts, e.g. `en`.
+    /// Episodes have no transcripts if unset.
+    pub(crate) transcript_language: Option<String>,
+    /// Deliver episodes as MP4 video instead of audio, no taller than the
+    /// profile's `max_height`.
+    pub(crate) video: bool,
+}
+
+impl Default for FeedSettings {
+    fn default() -> Self {
+        Self {
+            profile: None,
+            sponsorblock: None,
+            embargo_hours: 0,
+            sponsorblock_recheck_days: DEFAULT_SPONSORBLOCK_RECHECK_DAYS,
+            loudness_target_lufs: None,
+            trim_silence: None,
+            tempos: vec![],
+            transcript_language: None,
+            video: false,
+        }
+    }
+}
+
+impl FeedSettings {
+    pub(crate) fn embargo(&self) -> chrono::Duration {
+        chrono::Duration::hours(self.embargo_hours as i64)
+    }
+
+    pub(crate) fn sponsorblock_recheck(&self) -> chrono::Duration {
+        chrono::Duration::days(self.sponsorblock_recheck_days as i64)
+    }
+
+    /// Whether `tempo` is one of the variants this feed offers.
+    pub(crate) fn offers_tempo(&self, tempo: f64) -> bool {
+        self.tempos.contains(&tempo)
+    }
+
+    /// Whether times taken from the video, like those of its chapters and
+    /// captions, still hold for this feed's episodes. Trimming silence moves
+    /// everything after each pause it cuts.
+    pub(crate) fn keeps_video_timing(&self) -> bool {
+        self.trim_silence.is_none()
+    }
+
+    /// Language of the transcripts served with this feed's episodes, if any
+    /// are.
+    pub(crate) fn transcripts(&self) -> Option<&str> {
+        self.transcript_language
+            .as_deref()
+            .filter(|_| self.keeps_video_timing())
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            profiles: HashMap::from([(DEFAULT_PROFILE.to_string(), DownloadProfile::default())]),
+            feeds: HashMap::new(),
+            default_feed: FeedSettings::default(),
+        }
+    }
+}
+
+impl Config {
+    pub(crate) fn load(path: Option<&Path>) -> color_eyre::Result<Self> {
+        let Some(path) = path else {
+            return Ok(Self::default());
+        };
+
+        let text = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("reading config file {}", path.display()))?;
+        Self::parse(&text).wrap_err_with(|| format!("parsing config file {}", path.display()))
+    }
+
+    pub(crate) fn parse(text: &str) -> color_eyre::Result<Self> {
+        let mut config: Config = toml::from_str(text)?;
+        config
+            .profiles
+            .entry(DEFAULT_PROFILE.to_string())
+            .or_default();
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn validate(&self) -> color_eyre::Result<()> {
+        for (name, profile) in &self.profiles {
+            if profile.formats.is_empty() {
+                return Err(eyre!("profile '{name}' has no formats"));
+            }
+            if !(1..=DownloadProfile::MAX_CONCURRENT_FRAGMENTS)
+                .contains(&profile.concurrent_fragments)
+            {
+                return Err(eyre!(
+                    "profile '{name}' must use between 1 and {} concurrent fragments",
+                    DownloadProfile::MAX_CONCURRENT_FRAGMENTS
+                ));
+            }
+            if let Err(e) = check_streaming(profile) {
+                return Err(eyre!("profile '{name}' {e}"));
+            }
+            if profile.max_height < DownloadProfile::MIN_HEIGHT {
+                return Err(eyre!(
+                    "profile '{name}' must allow videos at least {} pixels tall",
+                    DownloadProfile::MIN_HEIGHT
+                ));
+            }
+        }
+
+        for (feed_id, settings) in &self.feeds {
+            if let Some(profile) = &settings.profile {
+                if !self.profiles.contains_key(profile) {
+                    return Err(eyre!("feed '{feed_id}' uses unknown profile '{profile}'"));
+                }
+            }
+            if let Err(e) = check_streaming(&self.profile(feed_id)) {
+                return Err(eyre!("feed '{feed_id}' {e}"));
+            }
+            if let Some(target) = settings.loudness_target_lufs {
+                if !LOUDNESS_TARGET_LUFS.contains(&target) {
+                    return Err(eyre!(
+                        "feed '{feed_id}' must target a loudness between {} and {} LUFS",
+                        LOUDNESS_TARGET_LUFS.start(),
+                        LOUDNESS_TARGET_LUFS.end()
+                    ));
+                }
+            }
+            if let Some(language) = &settings.transcript_language {
+                let valid = !language.is_empty()
+                    && language
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-');
+                if !valid || language.starts_with('-') {
+                    return Err(eyre!(
+                        "feed '{feed_id}' has invalid transcript language '{language}'"
+                    ));
+                }
+            }
+            // Both only work on the audio, which would drift from the picture.
+            if self.profile(feed_id).codec.is_video()
+                && (!settings.tempos.is_empty() || settings.trim_silence.is_some())
+            {
+                return Err(eyre!(
+                    "feed '{feed_id}' is delivered as video, which can't be sped up or trimmed"
+                ));
+            }
+            for tempo in &settings.tempos {
+                if *tempo == 1.0 || !audio::TEMPOS.contains(tempo) {
+                    return Err(eyre!(
+                        "feed '{feed_id}' has tempo {tempo}, tempos must be between {} and {} and not 1",
+                        audio::TEMPOS.start(),
+                        audio::TEMPOS.end()
+                    ));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The per-feed settings, as read from the file.
+    pub(crate) fn feeds(&self) -> &HashMap<String, FeedSettings> {
+        &self.feeds
+    }
+
+    pub(crate) fn feed(&self, feed_id: &str) -> &FeedSettings {
+        self.feeds.get(feed_id).unwrap_or(&self.default_feed)
+    }
+
+    /// The download profile for `feed_id`'s episodes, with the feed's own
+    /// overrides applied.
+    pub(crate) fn profile(&self, feed_id: &str) -> DownloadProfile {
+        let settings = self.feed(feed_id);
+        let name = settings.profile.as_deref().unwrap_or(DEFAULT_PROFILE);
+
+        let mut profile = self.profiles[name].clone();
+        if let Some(sponsorblock) = &settings.sponsorblock {
+            profile.sponsorblock = sponsorblock.clone();
+        }
+        if settings.video {
+            profile.codec = AudioFormat::Mp4;
+        }
+        profile
+    }
+}
+
+/// Streamed downloads skip yt-dlp's post-processing, which is what cuts
+/// SponsorBlock segments out, so the file would never match its feed.
+fn check_streaming(profile: &DownloadProfile) -> color_eyre::Result<()> {
+    if profile.stream_first_download && profile.sponsorblock.removes_segments() {
+        return Err(eyre!(
+            "streams first downloads, which can't have SponsorBlock segments removed"
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_feed_profile_builds_format_chain() {
+        let config = Config::parse(
+            r#"
+            [profiles.small]
+            formats = ["bestaudio[ext=m4a]", "worstaudio"]
+            max_bitrate = 64
+            sponsorblock = { mode = "off" }
+
+            [feeds.UCNmv1Cmjm3Hk8Vc9kIgv0AQ]
+            profile = "small"
+
+            [feeds.UCOGeU-1Fig3rrDjhm9Zs_wg]
+            sponsorblock = { mode = "remove", categories = ["sponsor", "music_offtopic"] }
+            "#,
+        )
+        .unwrap();
+
+        let profile = config.profile("UCNmv1Cmjm3Hk8Vc9kIgv0AQ");
+        assert_eq!(
+            profile.format(),
+            "bestaudio[ext=m4a][abr<=64]/worstaudio[abr<=64]"
+        );
+        assert!(!profile
+            .args(profile.codec)
+            .iter()
+            .any(|arg| arg.to_string().starts_with("--sponsorblock")));
+
+        let profile = config.profile("UCOGeU-1Fig3rrDjhm9Zs_wg");
+        assert!(profile.sponsorblock.removes_segments());
+        assert!(profile
+            .args(profile.codec)
+            .iter()
+            .any(|arg| arg.to_string() == "--sponsorblock-remove sponsor,music_offtopic"));
+    }
+
+    #[test]
+    fn test_flags_cannot_be_smuggled_in() {
+        let injected = Config::parse(
+            r#"
+            [profiles.evil]
+            formats = ["bestaudio --exec rm"]
+            "#,
+        );
+        assert!(injected.is_err());
+
+        let unknown = Config::parse(
+            r#"
+            [profiles.evil]
+            extra_args = ["--exec", "rm"]
+            "#,
+        );
+        assert!(unknown.is_err());
+    }
+
+    #[test]
+    fn test_loudness_target_is_validated() {
+        let config = Config::parse(
+            r#"
+            [feeds.UCNmv1Cmjm3Hk8Vc9kIgv0AQ]
+            loudness_target_lufs = -16
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.feed("UCNmv1Cmjm3Hk8Vc9kIgv0AQ").loudness_target_lufs,
+            Some(-16.0)
+        );
+        assert_eq!(config.feed("elsewhere").loudness_target_lufs, None);
+
+        let too_loud = Config::parse(
+            r#"
+            [feeds.UCNmv1Cmjm3Hk8Vc9kIgv0AQ]
+            loudness_target_lufs = 3
+            "#,
+        );
+        assert!(too_loud.is_err());
+    }
+
+    #[test]
+    fn test_streaming_is_not_combined_with_segment_removal() {
+        let profile = Config::parse(
+            r#"
+            [profiles.default]
+            stream_first_download = true
+            sponsorblock = { mode = "remove" }
+            "#,
+        );
+        assert!(profile.is_err());
+
+        let feed = Config::parse(
+            r#"
+            [profiles.default]
+            stream_first_download = true
+
+            [feeds.UCNmv1Cmjm3Hk8Vc9kIgv0AQ]
+            sponsorblock = { mode = "remove" }
+            "#,
+        );
+        assert!(feed.is_err());
+
+        let marked = Config::parse(
+            r#"
+            [profiles.default]
+            stream_first_download = true
+            sponsorblock = { mode = "mark" }
+            "#,
+        );
+        assert!(marked.is_ok());
+    }
+
+    #[test]
+    fn test_video_feeds_download_capped_mp4() {
+        let config = Config::parse(
+            r#"
+            [profiles.default]
+            max_height = 480
+
+            [feeds.UCsBjURrPoezykLs9EqgamOA]
+            video = true
+            "#,
+        )
+        .unwrap();
+
+        let profile = config.profile("UCsBjURrPoezykLs9EqgamOA");
+        assert_eq!(profile.codec, AudioFormat::Mp4);
+        let args: Vec<String> = profile
+            .args(profile.codec)
+            .iter()
+            .map(|arg| arg.to_string())
+            .collect();
+        assert!(args.contains(&"--merge-output-format mp4".to_string()));
+        assert!(args
+            .iter()
+            .any(|arg| arg.starts_with("--format bv*[height<=480][ext=mp4]+ba[ext=m4a]/")));
+        assert!(!args.contains(&"--extract-audio".to_string()));
+
+        let sped_up = Config::parse(
+            r#"
+            [feeds.UCsBjURrPoezykLs9EqgamOA]
+            video = true
+            tempos = [1.5]
+            "#,
+        );
+        assert!(sped_up.is_err());
+    }
+
+    #[test]
+    fn test_tempos_are_validated() {
+        let config = Config::parse(
+            r#"
+            [feeds.UCNmv1Cmjm3Hk8Vc9kIgv0AQ]
+            tempos = [1.5, 2]
+            "#,
+        )
+        .unwrap();
+        let settings = config.feed("UCNmv1Cmjm3Hk8Vc9kIgv0AQ");
+        assert!(settings.offers_tempo(2.0));
+        assert!(!settings.offers_tempo(1.25));
+
+        for tempo in ["1", "0.25", "8"] {
+            let config = Config::parse(&format!(
+                "[feeds.UCNmv1Cmjm3Hk8Vc9kIgv0AQ]\ntempos = [{tempo}]"
+            ));
+            assert!(config.is_err(), "tempo {tempo} was accepted");
+        }
+    }
+}
@@ -1,8 +1,11 @@
 use axum::{
-    http::StatusCode,
+    http::{header, StatusCode},
     response::{IntoResponse, Response},
 };
 
+/// How long clients are told to back off for when the download queue is full.
+const RETRY_AFTER_SECS: u64 = 30;
+
 pub type Result<T, E = Report> = color_eyre::Result<T, E>;
 pub struct Report(color_eyre::Report);
 
@@ -21,16 +24,33 @@ where
     }
 }
 
+impl Report {
+    /// The [`VpodError`] this was made from, if it was.
+    pub(crate) fn vpod_error(&self) -> Option<&VpodError> {
+        self.0.downcast_ref()
+    }
+
+    /// A copy for another caller waiting on the same work. A [`VpodError`]
+    /// stays itself so it is answered the same way, anything else keeps
+    /// only its messages.
+    pub(crate) fn copied(&self) -> Self {
+        match self.vpod_error() {
+            Some(e) => e.clone().into(),
+            None => Self(color_eyre::eyre::eyre!("{:#}", self.0)),
+        }
+    }
+}
+
 impl IntoResponse for Report {
     fn into_response(self) -> Response {
         let e = self.0;
         let e_str = format!("{e:?}");
 
-        tracing::error!("{e_str}");
-
         if let Some(e) = e.downcast_ref::<VpodError>() {
-            return e.response();
+            tracing::error!(reason = e.reason(), "{e_str}");
+            e.response()
         } else {
+            tracing::error!("{e_str}");
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Something went wrong".to_string(),
@@ -40,7 +60,7 @@ impl IntoResponse for Report {
     }
 }
 
-#[derive(thiserror::Error, Debug)]
+#[derive(thiserror::Error, Debug, Clone)]
 pub(crate) enum VpodError {
     #[error("could not find channel ID")]
     ChannelNotFound,
@@ -48,9 +68,51 @@ pub(crate) enum VpodError {
     PlaylistIdNotFound,
     #[error("error running youtube-dlp")]
     YoutubeDLError,
+    #[error("download queue is full")]
+    DownloadQueueFull,
+    #[error("video is private")]
+    VideoPrivate,
+    #[error("video is for channel members only")]
+    MembersOnly,
+    #[error("video is age-restricted")]
+    AgeRestricted,
+    #[error("video is not available in this region")]
+    GeoBlocked,
+    #[error("video is an upcoming premiere or live stream")]
+    NotYetAvailable,
+    #[error("video has been removed")]
+    VideoRemoved,
+    #[error("no audio format matching the download profile")]
+    FormatUnavailable,
+    #[error("feed does not offer this tempo")]
+    TempoNotOffered,
+    #[error("video has no captions in the feed's language")]
+    TranscriptUnavailable,
+    #[error("segments cut from the episode are not known")]
+    ChaptersUnavailable,
 }
 
 impl VpodError {
+    /// Short machine-friendly name, logged alongside the error.
+    pub(crate) fn reason(&self) -> &'static str {
+        match self {
+            Self::ChannelNotFound => "channel_not_found",
+            Self::PlaylistIdNotFound => "playlist_id_not_found",
+            Self::YoutubeDLError => "youtube_dl_error",
+            Self::DownloadQueueFull => "download_queue_full",
+            Self::VideoPrivate => "video_private",
+            Self::MembersOnly => "members_only",
+            Self::AgeRestricted => "age_restricted",
+            Self::GeoBlocked => "geo_blocked",
+            Self::NotYetAvailable => "not_yet_available",
+            Self::VideoRemoved => "video_removed",
+            Self::FormatUnavailable => "format_unavailable",
+            Self::TempoNotOffered => "tempo_not_offered",
+            Self::TranscriptUnavailable => "transcript_unavailable",
+            Self::ChaptersUnavailable => "chapters_unavailable",
+        }
+    }
+
     fn response(&self) -> Response {
         match self {
             Self::ChannelNotFound => (StatusCode::NOT_FOUND, "Channel not found").into_response(),
@@ -60,6 +122,45 @@ impl VpodError {
             Self::YoutubeDLError => {
                 (StatusCode::INTERNAL_SERVER_ERROR, "Error getting audio").into_response()
             }
+            Self::DownloadQueueFull => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                [(header::RETRY_AFTER, RETRY_AFTER_SECS.to_string())],
+                "Too many downloads in progress, try again later",
+            )
+                .into_response(),
+            Self::VideoPrivate => (StatusCode::FORBIDDEN, "Video is private").into_response(),
+            Self::MembersOnly => {
+                (StatusCode::FORBIDDEN, "Video is for channel members only").into_response()
+            }
+            Self::AgeRestricted => {
+                (StatusCode::FORBIDDEN, "Video is age-restricted").into_response()
+            }
+            Self::GeoBlocked => (
+                StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS,
+                "Video is not available in this region",
+            )
+                .into_response(),
+            Self::NotYetAvailable => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                [(header::RETRY_AFTER, RETRY_AFTER_SECS.to_string())],
+                "Video has not premiered yet",
+            )
+                .into_response(),
+            Self::VideoRemoved => (StatusCode::GONE, "Video has been removed").into_response(),
+            Self::FormatUnavailable => {
+                (StatusCode::BAD_GATEWAY, "No suitable audio format").into_response()
+            }
+            Self::TempoNotOffered => {
+                (StatusCode::NOT_FOUND, "Feed does not offer this tempo").into_response()
+            }
+            Self::TranscriptUnavailable => {
+                (StatusCode::NOT_FOUND, "Video has no captions").into_response()
+            }
+            Self::ChaptersUnavailable => (
+                StatusCode::NOT_FOUND,
+                "Chapters are not known until the episode is downloaded",
+            )
+                .into_response(),
         }
     }
 }
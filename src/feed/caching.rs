@@ -0,0 +1,81 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use axum::http::{header, HeaderMap};
+use chrono::{DateTime, Utc};
+
+/// Strong ETag for a response `body`. The hasher has fixed keys, so the tag
+/// stays the same across restarts.
+pub(super) fn etag(body: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    body.hash(&mut hasher);
+    format!("\"{:016x}\"", hasher.finish())
+}
+
+/// `time` as an HTTP date, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+pub(super) fn http_date(time: DateTime<Utc>) -> String {
+    time.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+/// Whether the client already has the response tagged `etag`, last modified
+/// at `modified`. `If-None-Match` wins over `If-Modified-Since` when the
+/// request has both.
+pub(super) fn is_not_modified(headers: &HeaderMap, etag: &str, modified: DateTime<Utc>) -> bool {
+    if let Some(if_none_match) = headers.get(header::IF_NONE_MATCH) {
+        let Ok(if_none_match) = if_none_match.to_str() else {
+            return false;
+        };
+        return if_none_match
+            .split(',')
+            .map(str::trim)
+            .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag);
+    }
+
+    headers
+        .get(header::IF_MODIFIED_SINCE)
+        .and_then(|since| since.to_str().ok())
+        .and_then(|since| DateTime::parse_from_rfc2822(since).ok())
+        .is_some_and(|since| modified <= since)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn headers(name: header::HeaderName, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_is_not_modified() {
+        let tag = etag("<rss/>");
+        let modified = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+        assert_eq!(http_date(modified), "Fri, 01 Mar 2024 12:00:00 GMT");
+        let not_modified = |headers: &HeaderMap| is_not_modified(headers, &tag, modified);
+
+        assert!(!not_modified(&HeaderMap::new()));
+
+        let matching = format!("\"other\", W/{tag}");
+        assert!(not_modified(&headers(header::IF_NONE_MATCH, &matching)));
+        assert!(not_modified(&headers(header::IF_NONE_MATCH, "*")));
+        let other = headers(header::IF_NONE_MATCH, &etag("<rss></rss>"));
+        assert!(!not_modified(&other));
+
+        let since = |date| headers(header::IF_MODIFIED_SINCE, date);
+        assert!(not_modified(&since("Fri, 01 Mar 2024 12:00:00 GMT")));
+        assert!(!not_modified(&since("Fri, 01 Mar 2024 11:59:59 GMT")));
+        assert!(!not_modified(&since("yesterday")));
+
+        // A stale tag isn't saved by a recent date.
+        let mut both = other.clone();
+        both.insert(
+            header::IF_MODIFIED_SINCE,
+            HeaderValue::from_static("Sat, 02 Mar 2024 00:00:00 GMT"),
+        );
+        assert!(!not_modified(&both));
+    }
+}
@@ -0,0 +1,343 @@
+use color_eyre::eyre::eyre;
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::error::Result;
+
+/// What is known about a video before downloading it.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct VideoDetails {
+    pub(crate) duration_secs: Option<u32>,
+    pub(crate) live: LiveStatus,
+    pub(crate) members_only: bool,
+    pub(crate) age_restricted: bool,
+    /// Smallest first.
+    pub(crate) thumbnails: Vec<Thumbnail>,
+    pub(crate) chapters: Vec<VideoChapter>,
+    pub(crate) categories: Vec<String>,
+    pub(crate) description: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum LiveStatus {
+    #[default]
+    NotLive,
+    Live,
+    Upcoming,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub(crate) struct Thumbnail {
+    pub(crate) url: String,
+    #[serde(default)]
+    pub(crate) width: u32,
+    #[serde(default)]
+    pub(crate) height: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct VideoChapter {
+    pub(crate) start_secs: f64,
+    pub(crate) end_secs: Option<f64>,
+    pub(crate) title: String,
+}
+
+impl VideoDetails {
+    /// Whether the video can be downloaded as an episode right now, live
+    /// streams and premieres can't until they are over.
+    pub(crate) fn is_listenable(&self) -> bool {
+        self.live == LiveStatus::NotLive && !self.members_only
+    }
+
+    /// The largest thumbnail.
+    pub(crate) fn thumbnail(&self) -> Option<&Thumbnail> {
+        self.thumbnails
+            .iter()
+            .max_by_key(|thumbnail| thumbnail.width * thumbnail.height)
+    }
+
+    /// From the player response and initial data a watch page embeds.
+    pub(crate) fn from_watch_page(page: &str) -> Result<Self> {
+        let player = embedded_json(page, "ytInitialPlayerResponse")
+            .ok_or(eyre!("watch page has no player response"))?;
+        let player: PlayerResponse = serde_json::from_str(player)?;
+        let status = player.playability_status;
+        let Some(video) = player.video_details else {
+            return Err(eyre!(
+                "video is unplayable: {}",
+                status.reason.as_deref().unwrap_or(&status.status)
+            )
+            .into());
+        };
+        let microformat = player
+            .microformat
+            .map(|microformat| microformat.player_microformat_renderer)
+            .unwrap_or_default();
+
+        let duration_secs = video.length_seconds.and_then(|secs| secs.parse().ok());
+        let chapters = embedded_json(page, "ytInitialData")
+            .and_then(|data| serde_json::from_str(data).ok())
+            .map(|data| chapters_in(&data, duration_secs))
+            .unwrap_or_default();
+        let reason = status.reason.unwrap_or_default().to_lowercase();
+
+        Ok(Self {
+            duration_secs,
+            live: match (video.is_live, video.is_upcoming) {
+                (_, true) => LiveStatus::Upcoming,
+                (true, _) => LiveStatus::Live,
+                _ => LiveStatus::NotLive,
+            },
+            members_only: reason.contains("members"),
+            age_restricted: status.status.starts_with("AGE_")
+                || reason.contains("confirm your age")
+                || microformat.is_family_safe == Some(false),
+            thumbnails: video.thumbnail.thumbnails,
+            chapters,
+            categories: microformat.category.into_iter().collect(),
+            description: video.short_description,
+        })
+    }
+
+    /// From what yt-dlp's `--dump-json` prints.
+    pub(crate) fn from_dump_json(json: &str) -> Result<Self> {
+        let info: DumpJson = serde_json::from_str(json)?;
+
+        Ok(Self {
+            duration_secs: info.duration.map(|secs| secs.round() as u32),
+            live: match info.live_status.as_deref() {
+                Some("is_live") => LiveStatus::Live,
+                Some("is_upcoming") => LiveStatus::Upcoming,
+                _ => LiveStatus::NotLive,
+            },
+            members_only: info.availability.as_deref() == Some("subscriber_only"),
+            age_restricted: info.age_limit.is_some_and(|age| age >= 18),
+            thumbnails: info.thumbnails,
+            chapters: info
+                .chapters
+                .unwrap_or_default()
+                .into_iter()
+                .map(|chapter| VideoChapter {
+                    start_secs: chapter.start_time,
+                    end_secs: Some(chapter.end_time),
+                    title: chapter.title,
+                })
+                .collect(),
+            categories: info.categories,
+            description: info.description.unwrap_or_default(),
+        })
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct PlayerResponse {
+    playability_status: PlayabilityStatus,
+    video_details: Option<PlayerVideoDetails>,
+    microformat: Option<Microformat>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct PlayabilityStatus {
+    status: String,
+    reason: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct PlayerVideoDetails {
+    length_seconds: Option<String>,
+    is_live: bool,
+    is_upcoming: bool,
+    short_description: String,
+    thumbnail: Thumbnails,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Thumbnails {
+    thumbnails: Vec<Thumbnail>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct Microformat {
+    player_microformat_renderer: MicroformatRenderer,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct MicroformatRenderer {
+    category: Option<String>,
+    is_family_safe: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct DumpJson {
+    duration: Option<f64>,
+    live_status: Option<String>,
+    availability: Option<String>,
+    age_limit: Option<u32>,
+    thumbnails: Vec<Thumbnail>,
+    chapters: Option<Vec<DumpChapter>>,
+    categories: Vec<String>,
+    description: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DumpChapter {
+    start_time: f64,
+    end_time: f64,
+    title: String,
+}
+
+/// The JSON object a page assigns to `name` in one of its scripts.
+fn embedded_json<'a>(page: &'a str, name: &str) -> Option<&'a str> {
+    let start = page.find(&format!("{name} = {{"))? + name.len() + 3;
+    let json = &page[start..];
+
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in json.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            '{' if !in_string => depth += 1,
+            '}' if !in_string => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(&json[..=i]);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    None
+}
+
+/// Chapters in the player bar of a watch page's initial data, each ending
+/// where the next starts.
+fn chapters_in(data: &Value, duration_secs: Option<u32>) -> Vec<VideoChapter> {
+    let mut starts = vec![];
+    collect_chapters(data, &mut starts);
+    starts.sort_by(|a, b| a.0.total_cmp(&b.0));
+    starts.dedup_by(|a, b| a.0 == b.0);
+
+    let ends = starts
+        .iter()
+        .skip(1)
+        .map(|(start, _)| Some(*start))
+        .chain([duration_secs.map(f64::from)]);
+    starts
+        .iter()
+        .zip(ends)
+        .map(|((start_secs, title), end_secs)| VideoChapter {
+            start_secs: *start_secs,
+            end_secs,
+            title: title.clone(),
+        })
+        .collect()
+}
+
+fn collect_chapters(value: &Value, starts: &mut Vec<(f64, String)>) {
+    match value {
+        Value::Object(object) => {
+            if let Some(chapter) = object.get("chapterRenderer") {
+                let start = chapter["timeRangeStartMillis"].as_f64();
+                let title = chapter["title"]["simpleText"].as_str();
+                if let (Some(start), Some(title)) = (start, title) {
+                    starts.push((start / 1000.0, title.to_string()));
+                }
+            }
+            object
+                .values()
+                .for_each(|value| collect_chapters(value, starts));
+        }
+        Value::Array(values) => values
+            .iter()
+            .for_each(|value| collect_chapters(value, starts)),
+        _ => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(path: &str) -> String {
+        let path = format!("{}/fixtures/youtube/{path}", env!("CARGO_MANIFEST_DIR"));
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn test_details_from_watch_page() {
+        let details = VideoDetails::from_watch_page(&fixture("watch/v=fixtureVid1")).unwrap();
+
+        assert_eq!(details.duration_secs, Some(754));
+        assert!(details.is_listenable());
+        assert!(!details.age_restricted);
+        assert_eq!(
+            details.thumbnail().unwrap().url,
+            "https://i.ytimg.com/vi/fixtureVid1/maxresdefault.jpg"
+        );
+        assert_eq!(details.categories, ["Gaming"]);
+        assert_eq!(
+            details.chapters,
+            [
+                VideoChapter {
+                    start_secs: 0.0,
+                    end_secs: Some(90.5),
+                    title: "Intro {the \"real\" one}".to_string(),
+                },
+                VideoChapter {
+                    start_secs: 90.5,
+                    end_secs: Some(754.0),
+                    title: "Lore".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unlistenable_videos() {
+        let upcoming = VideoDetails::from_watch_page(&fixture("watch/v=fixtureLive")).unwrap();
+        assert_eq!(upcoming.live, LiveStatus::Upcoming);
+        assert_eq!(upcoming.duration_secs, None);
+        assert!(!upcoming.is_listenable());
+
+        let members = VideoDetails::from_watch_page(&fixture("watch/v=fixtureMemb")).unwrap();
+        assert!(members.members_only);
+        assert!(!members.is_listenable());
+
+        assert!(VideoDetails::from_watch_page(&fixture("watch/v=fixtureGone")).is_err());
+        assert!(VideoDetails::from_watch_page("<html></html>").is_err());
+    }
+
+    #[test]
+    fn test_details_from_dump_json() {
+        let details = VideoDetails::from_dump_json(
+            r#"{
+                "duration": 180.4,
+                "live_status": "was_live",
+                "availability": "public",
+                "age_limit": 18,
+                "thumbnails": [{"url": "https://i.ytimg.com/vi/x/hq.jpg", "width": 480, "height": 360}],
+                "chapters": [{"start_time": 0.0, "end_time": 180.4, "title": "All of it"}],
+                "categories": ["Education"],
+                "description": "0:00 All of it"
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(details.duration_secs, Some(180));
+        assert!(details.is_listenable());
+        assert!(details.age_restricted);
+        assert_eq!(details.chapters[0].end_secs, Some(180.4));
+        assert_eq!(details.categories, ["Education"]);
+    }
+}
@@ -1,13 +1,15 @@
 use std::collections::BTreeMap;
 
 use chrono::Duration;
+use color_eyre::eyre::eyre;
 use rss::extension::{
     itunes::{ITunesItemExtension, ITunesItemExtensionBuilder},
     ExtensionBuilder,
 };
 
-use crate::cli::Cli;
-use clap::Parser;
+use super::VideoDetails;
+use crate::audio::{self, AudioFormat, EpisodeFile, TranscriptFormat};
+use crate::error::Report;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Episode {
@@ -17,10 +19,21 @@ pub struct Episode {
     pub title: String,
     pub duration_str: String,
     pub duration_secs: u32,
+    /// Size of the downloaded file, once there is one.
+    pub size_bytes: Option<u64>,
+    /// Language of the transcripts served with the episode, if any are.
+    pub transcript_language: Option<String>,
+    /// Whether chapters are served with the episode.
+    pub chapters: bool,
     pub author: String,
     pub date: String,
     pub link: String,
     pub description: String,
+    /// Episode artwork, the video's largest thumbnail.
+    pub image: Option<String>,
+    /// Whether the video is age-restricted.
+    pub explicit: bool,
+    pub categories: Vec<String>,
 }
 
 impl Episode {
@@ -35,6 +48,103 @@ impl Episode {
         self.link.to_owned()
     }
 
+    pub fn published(&self) -> Option<chrono::DateTime<chrono::FixedOffset>> {
+        chrono::DateTime::parse_from_rfc2822(&self.date).ok()
+    }
+
+    pub fn set_size(self, size_bytes: Option<u64>) -> Self {
+        Self { size_bytes, ..self }
+    }
+
+    /// The real file size if known, otherwise a guess from the duration.
+    pub fn enclosure_length(&self) -> u64 {
+        self.size_bytes
+            .unwrap_or(u64::from(self.duration_secs) * self.format().estimated_bytes_per_sec())
+    }
+
+    /// What the enclosure is delivered as, going by its URL.
+    pub(crate) fn format(&self) -> AudioFormat {
+        AudioFormat::from_file_name(self.file_name()).unwrap_or_default()
+    }
+
+    /// Point the enclosure at the `codec` variant of this episode.
+    pub(crate) fn set_codec(self, codec: AudioFormat) -> Self {
+        if AudioFormat::from_file_name(self.file_name()) == Some(codec) {
+            return self;
+        }
+
+        let stem_len = self.url.rfind('.').unwrap_or(self.url.len());
+        let url = format!("{}.{}", &self.url[..stem_len], codec.extension());
+        Self {
+            url,
+            size_bytes: None,
+            ..self
+        }
+    }
+
+    /// Point the enclosure at the `tempo` times sped-up variant of this
+    /// episode, with its duration shortened to match.
+    pub(crate) fn set_tempo(self, tempo: f64) -> Self {
+        let Some(file) = EpisodeFile::parse(self.file_name()) else {
+            return self;
+        };
+        let variant = EpisodeFile {
+            tempo: Some(tempo),
+            ..file
+        }
+        .file_name();
+
+        let url = format!(
+            "{}{variant}",
+            &self.url[..self.url.len() - self.file_name().len()]
+        );
+        let duration = (f64::from(self.duration_secs) / tempo).round() as u32;
+        Self {
+            url,
+            size_bytes: None,
+            ..self
+        }
+        .set_length(duration)
+    }
+
+    pub fn set_transcript_language(self, transcript_language: Option<String>) -> Self {
+        Self {
+            transcript_language,
+            ..self
+        }
+    }
+
+    pub fn set_chapters(self, chapters: bool) -> Self {
+        Self { chapters, ..self }
+    }
+
+    /// Fill in what is known about the video: its length, artwork, age
+    /// restriction and categories.
+    pub(crate) fn set_details(self, details: &VideoDetails) -> Self {
+        let length = details.duration_secs.unwrap_or(self.duration_secs);
+        Self {
+            image: details
+                .thumbnail()
+                .map(|thumbnail| thumbnail.url.clone())
+                .or(self.image),
+            explicit: details.age_restricted,
+            categories: details.categories.clone(),
+            ..self
+        }
+        .set_length(length)
+    }
+
+    /// Where the episode's Podcasting 2.0 chapters are served.
+    pub fn chapters_url(&self) -> String {
+        let stem_len = self.url.rfind('.').unwrap_or(self.url.len());
+        format!("{}{}", &self.url[..stem_len], audio::CHAPTERS_SUFFIX)
+    }
+
+    /// Name of the episode's file under its feed's directory.
+    pub fn file_name(&self) -> &str {
+        self.url.rsplit('/').next().unwrap_or_default()
+    }
+
     pub fn set_length(self, length: u32) -> Self {
         let duration_str = {
             let duration = Duration::seconds(length.into());
@@ -63,64 +173,101 @@ impl Episode {
 }
 
 impl Episode {
-    pub fn from_xml_video(video: yt_feed_xml::Video, feed_id: &str) -> Self {
-        let cli = Cli::parse();
+    pub(crate) fn from_xml_video(
+        video: yt_feed_xml::Video,
+        feed_id: &str,
+        codec: AudioFormat,
+        episode_url: &url::Url,
+    ) -> Self {
         Episode {
             id: rss::GuidBuilder::default().value(&video.id).build(),
             url: format!(
-                "{episode_host_url}ep/{feed_id}/{ep_id}.m4a",
-                episode_host_url = cli.episode_url,
+                "{episode_host_url}ep/{feed_id}/{ep_id}.{ext}",
+                episode_host_url = episode_url,
                 feed_id = feed_id,
-                ep_id = &video.id
+                ep_id = &video.id,
+                ext = codec.extension(),
             ),
             episode: None,
             title: video.title,
             duration_str: "00:30:00".to_string(),
             duration_secs: 1800,
+            size_bytes: None,
+            transcript_language: None,
+            chapters: true,
             author: video.author,
             date: video.published.to_rfc2822(),
             link: video.url,
             description: video.description,
+            image: Some(video.thumbnail).filter(|url| !url.is_empty()),
+            explicit: false,
+            categories: vec![],
         }
     }
 }
 
-impl From<rss::Item> for Episode {
-    fn from(item: rss::Item) -> Self {
-        let itunes_info = item.itunes_ext().expect("item had no itunesextension");
-        Episode {
-            id: item.guid().expect("could not find id").to_owned(),
-            url: item.enclosure().unwrap().url().to_owned(),
-            episode: itunes_info.episode().map(|value| {
+impl TryFrom<rss::Item> for Episode {
+    type Error = Report;
+
+    fn try_from(item: rss::Item) -> Result<Self, Self::Error> {
+        let id = item.guid().ok_or(eyre!("item has no guid"))?;
+        let itunes_info = item
+            .itunes_ext()
+            .ok_or_else(|| eyre!("item {} has no itunes extension", id.value()))?;
+        let missing = |field: &str| eyre!("item {} has no {field}", id.value());
+
+        let episode = match itunes_info.episode() {
+            Some(value) => Some(
                 value
                     .parse::<u32>()
-                    .unwrap_or_else(|_| panic!("could not parse {value} as i32"))
-            }),
-            title: item
-                .title()
-                .expect("could not find title in feed")
+                    .map_err(|_| eyre!("could not parse episode number {value}"))?,
+            ),
+            None => None,
+        };
+        let duration_secs = itunes_info
+            .duration()
+            .and_then(parse_duration)
+            .ok_or_else(|| missing("duration"))?;
+
+        Ok(Episode {
+            id: id.to_owned(),
+            url: item
+                .enclosure()
+                .ok_or_else(|| missing("enclosure"))?
+                .url()
                 .to_owned(),
-            duration_str: itunes_info
-                .duration()
-                .map(|v| v.to_owned())
-                .expect("could not find duration for this episode"),
-            duration_secs: item.enclosure()
-                .map(|enc| enc.length())
-                .map(|s| s.parse::<u32>().unwrap_or_else(|_| panic!("could not parse {s} as i64")))
-                .expect("could not compute duration_secs from the enclosure for the specified rss entry"),
-            author: itunes_info.author().expect("could not find author for specified episode").to_owned(),
-            date: item.pub_date().expect("could not find date for specified episode").to_owned(),
-            link: item.link().expect("could not find link for specified episode").to_owned(),
-            description: item.description().expect("could not find description for specified episode").to_owned(),
+            episode,
+            title: item.title().ok_or_else(|| missing("title"))?.to_owned(),
+            duration_str: String::new(),
+            duration_secs,
+            // Stored lengths may be estimates; the real size is filled in from
+            // the episode's metadata when the feed is rendered.
+            size_bytes: None,
+            transcript_language: None,
+            chapters: true,
+            author: itunes_info.author().unwrap_or_default().to_owned(),
+            date: item.pub_date().ok_or_else(|| missing("date"))?.to_owned(),
+            link: item.link().unwrap_or_default().to_owned(),
+            description: item.description().unwrap_or_default().to_owned(),
+            image: itunes_info.image().map(|image| image.to_owned()),
+            explicit: itunes_info.explicit() == Some("true"),
+            categories: item
+                .categories()
+                .iter()
+                .map(|category| category.name().to_owned())
+                .collect(),
         }
+        .set_length(duration_secs))
     }
 }
 
 impl From<Episode> for rss::Item {
     fn from(ep: Episode) -> Self {
+        let chapters_url = ep.chapters_url();
+        let stem_url = ep.url[..ep.url.rfind('.').unwrap_or(ep.url.len())].to_owned();
         let enclosure: rss::Enclosure = rss::EnclosureBuilder::default()
-            .mime_type("audio/x-m4a".to_owned())
-            .length(ep.duration_secs.to_string())
+            .mime_type(audio::mime_type(&ep.url).to_owned())
+            .length(ep.enclosure_length().to_string())
             .url(ep.url)
             .build();
 
@@ -128,6 +275,8 @@ impl From<Episode> for rss::Item {
             .episode(ep.episode.map(|ep| ep.to_string()))
             .author(Some(ep.author))
             .duration(Some(ep.duration_str))
+            .image(ep.image)
+            .explicit(Some(ep.explicit.to_string()))
             .block(Some("Yes".to_string()))
             .build();
 
@@ -142,11 +291,59 @@ impl From<Episode> for rss::Item {
                 .build()],
         )]);
 
+        let chapters = ep
+            .chapters
+            .then(|| {
+                ExtensionBuilder::default()
+                    .name("podcast:chapters".to_owned())
+                    .attrs(BTreeMap::from([
+                        ("url".to_owned(), chapters_url),
+                        ("type".to_owned(), "application/json+chapters".to_owned()),
+                    ]))
+                    .build()
+            })
+            .into_iter()
+            .collect();
+
+        let transcripts = ep
+            .transcript_language
+            .iter()
+            .flat_map(|language| {
+                TranscriptFormat::ALL.map(|format| {
+                    ExtensionBuilder::default()
+                        .name("podcast:transcript".to_owned())
+                        .attrs(BTreeMap::from([
+                            (
+                                "url".to_owned(),
+                                format!("{stem_url}.{}", format.extension()),
+                            ),
+                            ("type".to_owned(), format.mime_type().to_owned()),
+                            ("language".to_owned(), language.clone()),
+                            ("rel".to_owned(), "captions".to_owned()),
+                        ]))
+                        .build()
+                })
+            })
+            .collect();
+        let podcast = BTreeMap::from([
+            ("chapters".to_owned(), chapters),
+            ("transcript".to_owned(), transcripts),
+        ]);
+
         let item: rss::Item = rss::ItemBuilder::default()
             .guid(Some(ep.id))
             .pub_date(Some(ep.date))
             .title(Some(ep.title))
-            .extensions(BTreeMap::from([("itunes_title".to_owned(), itunes_title)])) // put <itunes:title> in there
+            .categories(
+                ep.categories
+                    .into_iter()
+                    .map(|name| rss::CategoryBuilder::default().name(name).build())
+                    .collect::<Vec<_>>(),
+            )
+            .extensions(BTreeMap::from([
+                ("itunes_title".to_owned(), itunes_title), // put <itunes:title> in there
+                ("podcast".to_owned(), podcast),
+            ]))
             .itunes_ext(Some(itunes_metadata))
             .enclosure(Some(enclosure))
             .link(Some(ep.link))
@@ -156,3 +353,23 @@ impl From<Episode> for rss::Item {
         item
     }
 }
+
+/// Parse an `itunes:duration`, which may be `HH:MM:SS`, `MM:SS` or seconds.
+fn parse_duration(duration: &str) -> Option<u32> {
+    duration.split(':').try_fold(0u32, |total, part| {
+        Some(total * 60 + part.trim().parse::<u32>().ok()?)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("01:02:03"), Some(3723));
+        assert_eq!(parse_duration("12:34"), Some(754));
+        assert_eq!(parse_duration("1800"), Some(1800));
+        assert_eq!(parse_duration("soon"), None);
+    }
+}
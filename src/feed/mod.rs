@@ -1,41 +1,65 @@
-use std::{
-    collections::{BTreeMap, HashMap},
-    io::BufReader,
-};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 use axum::{
-    extract::{Path, Query},
-    response::IntoResponse,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
 };
+use color_eyre::eyre::eyre;
 use futures::StreamExt;
-use hyper::body;
 use rss::{extension::itunes::ITunesChannelExtensionBuilder, ChannelBuilder, ImageBuilder, Item};
 use serde::Deserialize;
-use tower::ServiceExt;
 
+mod caching;
+mod details;
 mod episode;
+mod refresh;
+mod source;
+mod store;
 mod utils;
+#[cfg(test)]
+pub(crate) use details::VideoChapter;
+pub(crate) use details::VideoDetails;
 use episode::Episode;
+pub(crate) use refresh::{spawn_refresher, Refreshing};
+#[cfg(test)]
+pub(crate) use source::serve_fixtures;
+pub(crate) use source::{MetadataSource, YouTube, YOUTUBE_URL};
+pub(crate) use store::Store;
+use utils::FeedPage;
 
+use crate::audio::{self, AudioFormat, DownloadProfile, EpisodeMeta, SponsorBlock};
 use crate::error::{Result, VpodError};
+use crate::sponsorblock;
+use crate::state::AppState;
+use crate::upstream::Upstream;
 
-#[tracing::instrument]
+#[tracing::instrument(skip(state, headers))]
 pub async fn serve_feed(
+    State(state): State<AppState>,
     Path(YtPath { path_type, val }): Path<YtPath>,
     Query(query): Query<HashMap<String, String>>,
-    _request: axum::extract::Request,
-) -> Result<impl IntoResponse> {
-    let yt_url = match path_type.clone() {
-        YtPathType::Handle(handle) => format!("https://www.youtube.com/{handle}"),
+    headers: HeaderMap,
+) -> Result<Response> {
+    let yt_path = match path_type.clone() {
+        YtPathType::Handle(handle) => handle,
         YtPathType::Abbrev(type_string)
         | YtPathType::Full(type_string)
         | YtPathType::Video(type_string)
         | YtPathType::Playlist(type_string)
-        | YtPathType::User(type_string) => format!(
-            "https://www.youtube.com/{}/{}",
-            type_string,
-            val.expect("This path type must have a val")
+        // Playlists are found by their `list` query instead.
+        | YtPathType::User(type_string) => {
+            format!("{}/{}", type_string, val.as_deref().unwrap_or_default())
+        }
+    };
+
+    let tempo = match query.get("tempo") {
+        Some(tempo) => Some(
+            tempo
+                .parse::<f64>()
+                .map_err(|_| VpodError::TempoNotOffered)?,
         ),
+        None => None,
     };
 
     match path_type {
@@ -44,57 +68,163 @@ pub async fn serve_feed(
                 .get("list")
                 .ok_or(VpodError::PlaylistIdNotFound)?
                 .to_owned();
-            Ok(gen_rss(&pl_id, FeedType::Playlist, _request).await?)
+            gen_rss(&pl_id, FeedType::Playlist, None, tempo, &headers, &state).await
+        }
+        // The ID is right there, so there is nothing to look up.
+        YtPathType::Full(_) => {
+            let channel_id = val
+                .as_deref()
+                .and_then(|val| val.split('/').next())
+                .filter(|id| !id.is_empty())
+                .ok_or(VpodError::ChannelNotFound)?
+                .to_owned();
+            gen_rss(
+                &channel_id,
+                FeedType::Channel,
+                None,
+                tempo,
+                &headers,
+                &state,
+            )
+            .await
         }
         _ => {
-            let channel_id = utils::get_channel_id(&yt_url)
+            let path = yt_path.clone();
+            let stored = state
+                .store
+                .blocking(move |store| store.feed_at(&path))
+                .await?;
+            if let Some(channel_id) = stored {
+                return gen_rss(
+                    &channel_id,
+                    FeedType::Channel,
+                    None,
+                    tempo,
+                    &headers,
+                    &state,
+                )
+                .await;
+            }
+
+            let page = state
+                .source
+                .page(&yt_path)
                 .await
                 .map_err(|_| VpodError::ChannelNotFound)?;
-            Ok(gen_rss(&channel_id, FeedType::Channel, _request).await?)
+            let channel_id = page.channel_id.clone().ok_or(VpodError::ChannelNotFound)?;
+            // The page is the channel's own, so it isn't fetched again.
+            let page = Some(page);
+            let response = gen_rss(
+                &channel_id,
+                FeedType::Channel,
+                page,
+                tempo,
+                &headers,
+                &state,
+            )
+            .await?;
+            state
+                .store
+                .blocking(move |store| store.save_path(&yt_path, &channel_id))
+                .await?;
+            Ok(response)
         }
     }
 }
 
-#[tracing::instrument(fields(feed_id=feed_id, feed_type=format!("{feed_type}")))]
+/// Answer with the feed `feed_id` as it was last refreshed, refreshing it in
+/// the background if that was a while ago. Only a feed that has never been
+/// built is waited for.
+///
+/// Clients that send back the feed's ETag or a date since its last change get
+/// an empty 304 instead.
+#[tracing::instrument(skip(page, headers, state), fields(feed_id=feed_id, feed_type=format!("{feed_type}")))]
 async fn gen_rss(
     feed_id: &str,
     feed_type: FeedType,
-    request: axum::extract::Request,
-) -> Result<impl IntoResponse> {
-    let path = format!("{feed_id}/{feed_type}-{feed_id}.xml");
-    let path = std::path::Path::new(&path);
-
-    let service = tower_http::services::ServeFile::new(&path);
-
-    let feed = match &path.exists() {
-        true => {
-            let new_feed = Feed::new(&feed_id, feed_type);
-            let old_file = std::fs::File::open(&path).unwrap();
-            let new_feed = new_feed.await;
-
-            let old_feed: Feed = rss::Channel::read_from(BufReader::new(&old_file))
-                .unwrap()
-                .into();
+    page: Option<FeedPage>,
+    tempo: Option<f64>,
+    headers: &HeaderMap,
+    state: &AppState,
+) -> Result<Response> {
+    let settings = state.config.feed(feed_id);
+    if tempo.is_some_and(|tempo| !settings.offers_tempo(tempo)) {
+        return Err(VpodError::TempoNotOffered.into());
+    }
+    let profile = state.config.profile(feed_id);
+    let feed_dir = state.feed_dir(feed_id);
 
-            update_feed(new_feed, old_feed).await
+    let id = feed_id.to_owned();
+    let (stored, downloads) = state
+        .store
+        .blocking(move |store| Ok((store.load(&id)?, store.downloads(&id)?)))
+        .await?;
+    let feed = match stored {
+        Some(feed) => {
+            refresh::refresh_if_due(feed_id, state).await;
+            feed
         }
-        false => Feed::new(&feed_id, feed_type).await,
+        None => refresh::refresh(feed_id, feed_type, page, state).await?,
+    };
+
+    let feed = feed
+        .with_codec(profile.codec)
+        .with_downloaded_meta(&downloads);
+    let feed = match tempo {
+        Some(tempo) => feed.with_tempo(tempo).with_downloaded_meta(&downloads),
+        None => feed,
     };
+    let channel = rss::Channel::from(
+        feed.with_chapters(settings.keeps_video_timing())
+            .with_transcripts(settings.transcripts(), &feed_dir)
+            .without_embargoed(settings.embargo()),
+    );
 
-    let channel = rss::Channel::from(feed.clone());
+    let body = channel.to_string();
 
-    let prefix = path.parent().expect("could not parse parent path");
-    if prefix.exists() == false {
-        std::fs::create_dir_all(&prefix).expect("could not create directory for podcast...");
+    let etag = caching::etag(&body);
+    let variant = tempo.map(|tempo| tempo.to_string()).unwrap_or_default();
+    let modified = {
+        let (id, etag) = (feed_id.to_owned(), etag.clone());
+        state
+            .store
+            .blocking(move |store| store.rendered(&id, &variant, &etag))
+            .await?
+    };
+    let validators = [
+        (header::ETAG, etag.clone()),
+        (header::LAST_MODIFIED, caching::http_date(modified)),
+        (
+            header::CACHE_CONTROL,
+            format!("max-age={}", state.feed_max_age.as_secs()),
+        ),
+    ];
+    if caching::is_not_modified(headers, &etag, modified) {
+        return Ok((StatusCode::NOT_MODIFIED, validators).into_response());
     }
 
-    let file =
-        std::fs::File::create(&path).unwrap_or_else(|_| panic!("could not create {feed_id}.xml"));
-    channel.write_to(file).unwrap();
-
-    let result = service.oneshot(request).await;
+    Ok((
+        validators,
+        [(header::CONTENT_TYPE, "application/rss+xml; charset=utf-8")],
+        body,
+    )
+        .into_response())
+}
 
-    Ok(result)
+/// Enclosure length of `file_name` in the feed last built for `feed_id`.
+pub(crate) async fn stored_enclosure_length(
+    state: &AppState,
+    feed_id: &str,
+    file_name: &str,
+) -> Option<u64> {
+    let (feed_id, file_name) = (feed_id.to_owned(), file_name.to_owned());
+    state
+        .store
+        .blocking(move |store| store.enclosure_length(&feed_id, &file_name))
+        .await
+        .map_err(|e| tracing::warn!("could not look up stored enclosure: {e:?}"))
+        .ok()
+        .flatten()
 }
 
 #[derive(Deserialize)]
@@ -150,92 +280,234 @@ struct Feed {
     episodes: Option<Vec<Episode>>,
 }
 
-#[tracing::instrument]
-async fn add_episode_length(eps: Vec<Episode>) -> Vec<Episode> {
-    let https = hyper_tls::HttpsConnector::new();
-    let client = hyper::Client::builder().build::<_, hyper::Body>(https);
-
-    let uris = eps
-        .clone()
-        .into_iter()
-        .map(|ep| ep.get_yt_link())
-        .map(|s| s.parse::<hyper::http::Uri>().unwrap());
-
-    let urls = futures::stream::iter(uris).map(|uri| client.get(uri));
-
-    let urls = urls
-        .buffered(15)
-        .then(|res| async {
-            let res = res.expect("Error making request: {}");
-            body::to_bytes(res).await.expect("err reading body!")
-        })
-        .then(|body| async {
-            // TODO: I don't know why this is here?
-            // tokio::time::sleep(tokio::time::Duration::from_millis(5)).await;
-            let body = String::from_utf8(body.into_iter().collect()).unwrap();
-            let length = body.find("lengthSeconds");
-            match length {
-                Some(i) => {
-                    let text = &body[i + 16..];
-                    let end = text.find('"').unwrap();
-                    let text = &text[..end];
-                    text.parse::<u32>()
-                        .expect("could not parse duration as u32!")
+#[tracing::instrument(skip(sponsorblock, state))]
+async fn add_episode_length(
+    eps: Vec<Episode>,
+    sponsorblock: &SponsorBlock,
+    state: &AppState,
+) -> Vec<Episode> {
+    let eps: Vec<Episode> = futures::stream::iter(eps)
+        .map(|ep| async move {
+            match state.source.video_details(ep.id.value()).await {
+                Ok(details) if !details.is_listenable() => {
+                    tracing::info!(
+                        episode_id = ep.id.value(),
+                        live = ?details.live,
+                        members_only = details.members_only,
+                        "leaving out video that can't be listened to"
+                    );
+                    None
+                }
+                Ok(details) => Some(ep.set_details(&details)),
+                Err(e)
+                    if matches!(
+                        e.vpod_error(),
+                        Some(
+                            VpodError::MembersOnly
+                                | VpodError::VideoPrivate
+                                | VpodError::VideoRemoved
+                                | VpodError::NotYetAvailable
+                        )
+                    ) =>
+                {
+                    tracing::info!(
+                        episode_id = ep.id.value(),
+                        "leaving out video that can't be listened to: {e:?}"
+                    );
+                    None
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        episode_id = ep.id.value(),
+                        "could not get video details, keeping estimated length: {e:?}"
+                    );
+                    Some(ep)
                 }
-                None => 1800,
             }
         })
-        .collect::<Vec<u32>>()
+        .buffered(15)
+        .filter_map(|ep| async { ep })
+        .collect()
         .await;
 
-    eps.into_iter()
-        .zip(urls.into_iter())
-        .map(|(episode, length)| episode.set_length(length))
+    if sponsorblock.removes_segments() {
+        subtract_sponsor_segments(eps, sponsorblock, &state.sponsorblock_api).await
+    } else {
+        eps
+    }
+}
+
+/// Shorten each episode by the SponsorBlock segments yt-dlp will cut out of it.
+#[tracing::instrument(skip(eps, sponsorblock, api))]
+async fn subtract_sponsor_segments(
+    eps: Vec<Episode>,
+    sponsorblock: &SponsorBlock,
+    api: &Upstream,
+) -> Vec<Episode> {
+    futures::stream::iter(eps)
+        .map(|ep| async move {
+            match sponsorblock::segments(api, ep.id.value(), &sponsorblock.categories).await {
+                Ok(segments) => {
+                    let removed = sponsorblock::removed_secs(&segments);
+                    let length = ep.duration_secs.saturating_sub(removed);
+                    ep.set_length(length)
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        episode_id = ep.id.value(),
+                        "could not get SponsorBlock segments: {e:?}"
+                    );
+                    ep
+                }
+            }
+        })
+        .buffered(15)
         .collect()
+        .await
 }
 
-#[tracing::instrument]
-async fn update_feed(new_feed: Feed, old_feed: Feed) -> Feed {
-    let old_eps = old_feed.episodes.unwrap();
-    let mut new_eps = new_feed.episodes.as_ref().unwrap().to_owned();
+/// SponsorBlock segments keep coming in for days after a video goes up.
+/// Episodes published within `window` are measured again, and their cached
+/// audio dropped if it was made with segments that have since changed.
+#[tracing::instrument(skip(feed, sponsorblock, state))]
+async fn recheck_sponsor_segments(
+    feed: Feed,
+    feed_dir: &std::path::Path,
+    sponsorblock: &SponsorBlock,
+    window: chrono::Duration,
+    state: &AppState,
+) -> Feed {
+    let since = chrono::Utc::now() - window;
 
-    let tail = old_eps.last().unwrap();
+    let episodes = futures::stream::iter(feed.episodes.clone().unwrap_or_default())
+        .map(|ep| async move {
+            if ep.published().is_none_or(|published| published < since) {
+                return ep;
+            }
 
-    let start_index = match new_eps
-        .iter()
-        .rev()
-        .position(|ep| ep.id.value() == tail.id.value())
-    {
-        Some(i) => i + 1,
-        // TODO: fix how these are ordered so we don't have to do this
-        None => 0,
-    };
+            if let Err(e) = audio::recheck_segments(
+                &state.store,
+                &state.sponsorblock_api,
+                feed_dir,
+                ep.file_name(),
+                sponsorblock,
+            )
+            .await
+            {
+                tracing::warn!(
+                    episode_id = ep.id.value(),
+                    "could not recheck segments: {e:?}"
+                );
+            }
 
-    // TODO: what if the new feed is entirely new?? I don't think I've accounted for this
-    let eps = if start_index == 1 {
-        old_eps
-    } else {
-        let new_eps = add_episode_length(new_eps.drain(start_index..).collect()).await;
-        old_eps
-            .into_iter()
-            .chain(new_eps.into_iter())
-            .enumerate()
-            .map(|(count, ep)| ep.set_ep_number(Some(count.try_into().unwrap())))
-            .collect()
-    };
+            if sponsorblock.removes_segments() {
+                add_episode_length(vec![ep.clone()], sponsorblock, state)
+                    .await
+                    .pop()
+                    .unwrap_or(ep)
+            } else {
+                ep
+            }
+        })
+        .buffered(15)
+        .collect()
+        .await;
 
     Feed {
-        episodes: Some(eps),
+        episodes: Some(episodes),
+        ..feed
+    }
+}
+
+/// `new_feed` with its episodes merged into those of `old_feed`.
+fn update_feed(mut new_feed: Feed, old_feed: Feed) -> Feed {
+    let old_eps = old_feed.episodes.unwrap_or_default();
+    let new_eps = new_feed.episodes.take().unwrap_or_default();
+
+    Feed {
+        episodes: Some(merge_episodes(old_eps, new_eps)),
         ..new_feed
     }
 }
 
-#[derive(Debug)]
+/// Merge the episodes YouTube lists now into the stored ones, matching them
+/// up by video ID.
+///
+/// YouTube only lists a feed's latest videos, so stored episodes older than
+/// all of them are kept. Stored episodes it should list but doesn't were
+/// deleted, made private or can't be listened to anymore, and are dropped.
+/// Listed episodes take what YouTube says about them now. Episode numbers
+/// never change once given out: a re-upload, a listed video with the title
+/// of one just dropped, takes over its number, other new episodes are
+/// numbered after every episode there has been.
+fn merge_episodes(old_eps: Vec<Episode>, new_eps: Vec<Episode>) -> Vec<Episode> {
+    let window_start = new_eps.iter().filter_map(Episode::published).min();
+    let listed: HashSet<&str> = new_eps.iter().map(|ep| ep.id.value()).collect();
+    let mut next_number = old_eps
+        .iter()
+        .filter_map(|ep| ep.episode)
+        .max()
+        .map_or(0, |number| number + 1);
+
+    let mut numbers = HashMap::new();
+    let mut dropped = vec![];
+    let mut eps = vec![];
+    for ep in old_eps {
+        if listed.contains(ep.id.value()) {
+            numbers.insert(ep.id.value().to_owned(), ep.episode);
+        } else if window_start.is_some_and(|start| ep.published().is_some_and(|p| p >= start)) {
+            tracing::info!(
+                episode_id = ep.id.value(),
+                "dropping episode no longer listed"
+            );
+            dropped.push(ep);
+        } else {
+            eps.push(ep);
+        }
+    }
+
+    for ep in new_eps {
+        let number = match numbers.get(ep.id.value()) {
+            Some(number) => *number,
+            None => dropped
+                .iter()
+                .position(|old| old.title == ep.title)
+                .and_then(|i| dropped.remove(i).episode),
+        };
+        eps.push(ep.set_ep_number(number));
+    }
+
+    // Oldest first, however YouTube ordered them. Undated ones come first.
+    eps.sort_by_key(Episode::published);
+    eps.into_iter()
+        .map(|ep| match ep.episode {
+            Some(_) => ep,
+            None => {
+                next_number += 1;
+                ep.set_ep_number(Some(next_number - 1))
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy)]
 enum FeedType {
     Channel,
     Playlist,
 }
 
+impl std::str::FromStr for FeedType {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "channel" => Ok(Self::Channel),
+            "playlist" => Ok(Self::Playlist),
+            _ => Err(eyre!("unknown feed type {s}")),
+        }
+    }
+}
+
 impl std::fmt::Display for FeedType {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         let s = match self {
@@ -247,75 +519,183 @@ impl std::fmt::Display for FeedType {
 }
 
 impl Feed {
-    async fn new(id: &str, feed_type: FeedType) -> Self {
+    /// Point every enclosure at `codec`, including those of episodes added
+    /// while the feed used another one.
+    fn with_codec(self, codec: AudioFormat) -> Self {
+        let episodes = self
+            .episodes
+            .map(|eps| eps.into_iter().map(|ep| ep.set_codec(codec)).collect());
+
+        Feed { episodes, ..self }
+    }
+
+    /// Advertise chapters for every episode, or for none.
+    fn with_chapters(self, chapters: bool) -> Self {
+        let episodes = self.episodes.map(|eps| {
+            eps.into_iter()
+                .map(|ep| ep.set_chapters(chapters))
+                .collect()
+        });
+
+        Feed { episodes, ..self }
+    }
+
+    /// Advertise transcripts in `language` for every episode, or none.
+    /// Episodes recently found to have no captions in it are left out.
+    fn with_transcripts(self, language: Option<&str>, feed_dir: &std::path::Path) -> Self {
+        let episodes = self.episodes.map(|eps| {
+            eps.into_iter()
+                .map(|ep| {
+                    let language = language.filter(|language| {
+                        !audio::transcript_unavailable(feed_dir, ep.id.value(), language)
+                    });
+                    ep.set_transcript_language(language.map(str::to_owned))
+                })
+                .collect()
+        });
+
+        Feed { episodes, ..self }
+    }
+
+    /// Point every enclosure at the `tempo` times sped-up variant.
+    fn with_tempo(self, tempo: f64) -> Self {
+        let episodes = self
+            .episodes
+            .map(|eps| eps.into_iter().map(|ep| ep.set_tempo(tempo)).collect());
+
+        Feed { episodes, ..self }
+    }
+
+    /// Use the real size and duration of every episode that has been
+    /// downloaded, going by the feed's `downloads` by file name.
+    fn with_downloaded_meta(self, downloads: &HashMap<String, EpisodeMeta>) -> Self {
+        let episodes = self.episodes.map(|eps| {
+            eps.into_iter()
+                .map(|ep| {
+                    let Some(meta) = downloads.get(ep.file_name()) else {
+                        return ep;
+                    };
+                    let ep = ep.set_size(meta.size_bytes);
+                    match meta.duration_secs {
+                        Some(duration) => ep.set_length(duration),
+                        None => ep,
+                    }
+                })
+                .collect()
+        });
+
+        Feed { episodes, ..self }
+    }
+
+    /// Leave out episodes published less than `embargo` ago.
+    fn without_embargoed(self, embargo: chrono::Duration) -> Self {
+        let cutoff = chrono::Utc::now() - embargo;
+        let episodes = self.episodes.map(|eps| {
+            eps.into_iter()
+                .filter(|ep| ep.published().is_none_or(|published| published <= cutoff))
+                .collect()
+        });
+
+        Feed { episodes, ..self }
+    }
+
+    /// The feed `id` as it is now. Its page is fetched alongside its videos
+    /// unless it already was.
+    async fn new(
+        id: &str,
+        feed_type: FeedType,
+        page: Option<FeedPage>,
+        profile: &DownloadProfile,
+        state: &AppState,
+    ) -> Result<Self> {
+        let page = async {
+            match page {
+                Some(page) => Ok(page),
+                None => match feed_type {
+                    FeedType::Channel => state.source.page(&format!("channel/{id}")).await,
+                    FeedType::Playlist => state.source.page(&format!("playlist?list={id}")).await,
+                },
+            }
+        };
         match feed_type {
             FeedType::Channel => {
-                let feed = yt_feed_xml::Channel::new(id).await;
-                Feed::from_yt_channel(feed).await
+                let (feed, page) = tokio::try_join!(state.source.channel(id), page)?;
+                Feed::from_yt_channel(feed, page, profile, state).await
             }
             FeedType::Playlist => {
-                let feed = yt_feed_xml::Playlist::new(id).await;
-                Feed::from_yt_playlist(feed).await
+                let (feed, page) = tokio::try_join!(state.source.playlist(id), page)?;
+                Feed::from_yt_playlist(feed, page, profile, state).await
             }
         }
     }
 
-    async fn from_yt_channel(channel: yt_feed_xml::Channel) -> Self {
-        let channel_image = utils::get_feed_image(&channel.url).await.unwrap();
-        let channel_description = utils::get_feed_description(&channel.url).await.unwrap();
+    async fn from_yt_channel(
+        channel: yt_feed_xml::Channel,
+        page: FeedPage,
+        profile: &DownloadProfile,
+        state: &AppState,
+    ) -> Result<Self> {
         let channel_id = channel.id;
 
         let episodes: Vec<yt_feed_xml::Video> = channel
             .videos
-            .expect("this channel should have at least one video");
+            .ok_or_else(|| eyre!("channel {channel_id} came without its videos"))?;
 
-        let episodes: Vec<Episode> = process_videos(episodes, &channel_id).await;
+        let episodes: Vec<Episode> = process_videos(episodes, &channel_id, profile, state).await;
 
-        Feed {
-            image: channel_image,
+        Ok(Feed {
+            image: page.image,
             title: match std::env::var("ENV") {
                 Ok(var) if var == "staging" => format!("[β] {}", channel.title),
                 _ => channel.title,
             },
             author: channel.author,
-            description: channel_description,
+            description: page.description,
             link: channel.url,
             episodes: Some(episodes),
-        }
+        })
     }
 
-    async fn from_yt_playlist(pl: yt_feed_xml::Playlist) -> Self {
-        let image = utils::get_feed_image(&pl.url).await.unwrap();
-        let description = utils::get_feed_description(&pl.url).await.unwrap();
+    async fn from_yt_playlist(
+        pl: yt_feed_xml::Playlist,
+        page: FeedPage,
+        profile: &DownloadProfile,
+        state: &AppState,
+    ) -> Result<Self> {
         let pl_id = pl.id;
 
         let episodes: Vec<yt_feed_xml::Video> = pl
             .videos
-            .expect("this playlist should have at least one video");
+            .ok_or_else(|| eyre!("playlist {pl_id} came without its videos"))?;
 
-        let episodes: Vec<Episode> = process_videos(episodes, &pl_id).await;
+        let episodes: Vec<Episode> = process_videos(episodes, &pl_id, profile, state).await;
 
-        Feed {
-            image,
+        Ok(Feed {
+            image: page.image,
             title: match std::env::var("ENV") {
                 Ok(var) if var == "staging" => format!("[β] {}", pl.title),
                 _ => pl.title,
             },
             author: pl.author,
-            description,
+            description: page.description,
             link: pl.url,
             episodes: Some(episodes),
-        }
+        })
     }
 }
 
-async fn process_videos(vids: Vec<yt_feed_xml::Video>, feed_id: &str) -> Vec<Episode> {
+async fn process_videos(
+    vids: Vec<yt_feed_xml::Video>,
+    feed_id: &str,
+    profile: &DownloadProfile,
+    state: &AppState,
+) -> Vec<Episode> {
     let eps = vids
         .into_iter()
-        .map(|v| Episode::from_xml_video(v, feed_id))
+        .map(|v| Episode::from_xml_video(v, feed_id, profile.codec, &state.episode_url))
         .collect();
 
-    let eps = add_episode_length(eps).await;
+    let eps = add_episode_length(eps, &profile.sponsorblock, state).await;
 
     eps.into_iter()
         .filter(|ep| ep.duration_secs > 65)
@@ -337,6 +717,10 @@ impl From<Feed> for rss::Channel {
                 "content".to_owned(),
                 "http://purl.org/rss/1.0/modules/content/".to_owned(),
             ),
+            (
+                "podcast".to_owned(),
+                "https://podcastindex.org/namespace/1.0".to_owned(),
+            ),
         ]);
 
         let itunes_metadata = ITunesChannelExtensionBuilder::default()
@@ -363,23 +747,428 @@ impl From<Feed> for rss::Channel {
     }
 }
 
-impl From<rss::Channel> for Feed {
-    fn from(channel: rss::Channel) -> Self {
-        let itunes_metadata = channel.itunes_ext().unwrap();
+/// Feeds written as XML by earlier versions are read back with this. Items
+/// that can't be read are left out rather than failing the whole feed.
+impl TryFrom<rss::Channel> for Feed {
+    type Error = crate::error::Report;
+
+    fn try_from(channel: rss::Channel) -> Result<Self> {
+        let author = channel
+            .itunes_ext()
+            .and_then(|itunes| itunes.author())
+            .unwrap_or_default()
+            .to_string();
+        let image = channel
+            .image()
+            .ok_or(eyre!("feed {} has no image", channel.title()))?
+            .url()
+            .to_string();
         let episodes: Vec<Episode> = channel
-            .clone()
-            .into_items()
-            .into_iter()
-            .map(Episode::from)
+            .items()
+            .iter()
+            .cloned()
+            .filter_map(|item| {
+                Episode::try_from(item)
+                    .map_err(|e| tracing::warn!("leaving out unreadable item: {e:?}"))
+                    .ok()
+            })
             .collect();
 
-        Feed {
+        Ok(Feed {
             title: channel.title().to_string(),
-            image: channel.image().unwrap().url().to_string(),
-            author: itunes_metadata.author().unwrap().to_string(),
+            image,
+            author,
             description: channel.description().to_string(),
             link: channel.link().to_string(),
             episodes: Some(episodes),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::header, http::Request, http::StatusCode, Router};
+    use tower::ServiceExt;
+
+    use super::{
+        add_episode_length, merge_episodes, source::fixture_videos, source::MetadataSource,
+        utils::FeedPage, Episode, Feed, VideoDetails,
+    };
+    use crate::audio::AudioFormat;
+    use crate::error::{Result, VpodError};
+    use crate::state::test_state;
+
+    const CHANNEL_ID: &str = "UCfixture0000000000000000";
+    const PLAYLIST_ID: &str = "PLfixture0000000000000000000000000";
+
+    /// The whole app, building feeds from recorded fixtures.
+    async fn app(test: &str) -> (Router, std::path::PathBuf) {
+        let (state, data_dir) = test_state(
+            test,
+            r#"
+            [profiles.default]
+            sponsorblock = { mode = "off" }
+            "#,
+            &[],
+        )
+        .await;
+
+        (crate::router(state), data_dir)
+    }
+
+    async fn get_feed(app: &Router, uri: &str) -> rss::Channel {
+        let response = app
+            .clone()
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        rss::Channel::read_from(&body[..]).unwrap()
+    }
+
+    fn enclosures(channel: &rss::Channel) -> Vec<&str> {
+        channel
+            .items()
+            .iter()
+            .map(|item| item.enclosure().unwrap().url())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_channel_feed_from_fixtures() {
+        let (app, data_dir) = app("channel-feed").await;
+
+        let channel = get_feed(&app, "/@fixture").await;
+        assert_eq!(channel.title(), "Fixture Channel");
+        assert_eq!(
+            channel.image().unwrap().url(),
+            "https://yt3.googleusercontent.com/fixture=s900"
+        );
+        // The short and the upcoming live stream are left out.
+        assert_eq!(
+            enclosures(&channel),
+            [
+                format!("http://localhost/ep/{CHANNEL_ID}/fixtureVid1.m4a"),
+                format!("http://localhost/ep/{CHANNEL_ID}/fixtureVid2.m4a"),
+            ]
+        );
+        let durations: Vec<_> = channel
+            .items()
+            .iter()
+            .map(|item| item.itunes_ext().unwrap().duration().unwrap())
+            .collect();
+        assert_eq!(durations, ["00:12:34", "00:21:52"]);
+        let artwork = channel.items()[0].itunes_ext().unwrap().image();
+        assert_eq!(
+            artwork,
+            Some("https://i.ytimg.com/vi/fixtureVid1/maxresdefault.jpg")
+        );
+        assert_eq!(channel.items()[0].categories()[0].name(), "Gaming");
+        // Feeds are kept in the store, not written out as XML.
+        assert!(!data_dir
+            .join(format!("{CHANNEL_ID}/channel-{CHANNEL_ID}.xml"))
+            .exists());
+
+        // The second time round it is answered from the store.
+        let updated = get_feed(&app, &format!("/channel/{CHANNEL_ID}")).await;
+        assert_eq!(enclosures(&updated), enclosures(&channel));
+
+        std::fs::remove_dir_all(&data_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_playlist_feed_from_fixtures() {
+        let (app, data_dir) = app("playlist-feed").await;
+
+        let channel = get_feed(&app, &format!("/playlist?list={PLAYLIST_ID}")).await;
+        assert_eq!(channel.title(), "Fixture Playlist");
+        assert_eq!(
+            channel.description(),
+            "A playlist recorded for offline tests."
+        );
+        assert_eq!(channel.items().len(), 2);
+
+        std::fs::remove_dir_all(&data_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_unchanged_feed_is_not_sent_again() {
+        let (app, data_dir) = app("conditional-get").await;
+        let uri = format!("/channel/{CHANNEL_ID}");
+        let get = |header: Option<(header::HeaderName, String)>| {
+            let mut request = Request::get(&uri);
+            if let Some((name, value)) = header {
+                request = request.header(name, value);
+            }
+            app.clone().oneshot(request.body(Body::empty()).unwrap())
+        };
+
+        let response = get(None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let headers = response.headers();
+        assert_eq!(headers[header::CACHE_CONTROL], "max-age=900");
+        let etag = headers[header::ETAG].to_str().unwrap().to_owned();
+        let modified = headers[header::LAST_MODIFIED].to_str().unwrap().to_owned();
+
+        let response = get(Some((header::IF_NONE_MATCH, etag.clone())))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()[header::ETAG], etag.as_str());
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(body.is_empty());
+
+        let response = get(Some((header::IF_MODIFIED_SINCE, modified.clone())))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers()[header::LAST_MODIFIED], modified.as_str());
+
+        // A tag from before the feed changed gets the whole feed.
+        let response = get(Some((header::IF_NONE_MATCH, "\"stale\"".to_owned())))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        std::fs::remove_dir_all(&data_dir).unwrap();
+    }
+
+    #[test]
+    fn test_transcripts_known_missing_are_not_advertised() {
+        let feed_dir =
+            std::env::temp_dir().join(format!("vpod-transcripts-test-{}", std::process::id()));
+        std::fs::create_dir_all(&feed_dir).unwrap();
+        std::fs::write(feed_dir.join("mergeEp0002.en.no-captions"), "").unwrap();
+        let feed = Feed {
+            image: String::new(),
+            title: "Fixture Channel".to_string(),
+            author: "Fixture Channel".to_string(),
+            description: String::new(),
+            link: String::new(),
+            episodes: Some(merge_episodes(vec![], listed("merge-before"))),
+        };
+
+        let episodes = feed
+            .with_transcripts(Some("en"), &feed_dir)
+            .episodes
+            .unwrap();
+        let languages: Vec<_> = episodes
+            .iter()
+            .map(|ep| (ep.id.value(), ep.transcript_language.as_deref()))
+            .collect();
+        assert_eq!(
+            languages,
+            [
+                ("mergeEp0001", Some("en")),
+                ("mergeEp0002", None),
+                ("mergeBonus1", Some("en")),
+                ("mergeEp0003", Some("en")),
+            ]
+        );
+
+        std::fs::remove_dir_all(&feed_dir).unwrap();
+    }
+
+    #[test]
+    fn test_chapters_can_be_left_out() {
+        let feed = || Feed {
+            image: String::new(),
+            title: "Fixture Channel".to_string(),
+            author: "Fixture Channel".to_string(),
+            description: String::new(),
+            link: String::new(),
+            episodes: Some(listed("merge-before")),
+        };
+
+        let with = rss::Channel::from(feed().with_chapters(true)).to_string();
+        assert!(with.contains("podcast:chapters"));
+        let without = rss::Channel::from(feed().with_chapters(false)).to_string();
+        assert!(!without.contains("podcast:chapters"));
+    }
+
+    /// The episodes YouTube lists in the fixture feed `name`.
+    fn listed(name: &str) -> Vec<Episode> {
+        let episode_url = "http://localhost/".parse().unwrap();
+        fixture_videos(&format!("feeds/{name}.xml"))
+            .into_iter()
+            .map(|video| {
+                Episode::from_xml_video(video, CHANNEL_ID, AudioFormat::default(), &episode_url)
+            })
+            .collect()
+    }
+
+    fn numbers(eps: &[Episode]) -> Vec<(&str, Option<u32>)> {
+        eps.iter().map(|ep| (ep.id.value(), ep.episode)).collect()
+    }
+
+    #[test]
+    fn test_merge_keeps_episode_numbers() {
+        let stored = merge_episodes(vec![], listed("merge-before"));
+        assert_eq!(
+            numbers(&stored),
+            [
+                ("mergeEp0001", Some(0)),
+                ("mergeEp0002", Some(1)),
+                ("mergeBonus1", Some(2)),
+                ("mergeEp0003", Some(3)),
+            ]
+        );
+
+        // The bonus episode's number isn't given out again, the re-upload of
+        // episode three keeps its own.
+        let merged = merge_episodes(stored, listed("merge-after"));
+        assert_eq!(
+            numbers(&merged),
+            [
+                ("mergeEp0001", Some(0)),
+                ("mergeEp0002", Some(1)),
+                ("mergeEp0003b", Some(3)),
+                ("mergeEp0004", Some(4)),
+            ]
+        );
+        assert_eq!(merged[1].title, "Episode two (remastered)");
+
+        assert_eq!(
+            merge_episodes(merged.clone(), listed("merge-after")),
+            merged
+        );
+    }
+
+    #[test]
+    fn test_merge_past_stored_episodes() {
+        let stored = merge_episodes(vec![], listed("merge-before"));
+        let stored = merge_episodes(stored, listed("merge-after"));
+
+        // None of the stored episodes are listed anymore, and none are lost.
+        let merged = merge_episodes(stored.clone(), listed("merge-later"));
+        assert_eq!(merged[..4], stored[..]);
+        assert_eq!(
+            numbers(&merged[4..]),
+            [("mergeEp0005", Some(5)), ("mergeEp0006", Some(6))]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_feed_without_videos_is_an_error() {
+        let (state, data_dir) = test_state("no-videos", "", &[]).await;
+        let page = || FeedPage {
+            channel_id: Some(CHANNEL_ID.to_string()),
+            image: String::new(),
+            description: String::new(),
+        };
+        let profile = state.config.profile(CHANNEL_ID);
+
+        let channel = yt_feed_xml::Channel {
+            id: CHANNEL_ID.to_string(),
+            title: "Fixture Channel".to_string(),
+            author: "Fixture Channel".to_string(),
+            url: String::new(),
+            published: chrono::Utc::now(),
+            videos: None,
+        };
+        assert!(Feed::from_yt_channel(channel, page(), &profile, &state)
+            .await
+            .is_err());
+
+        let playlist = yt_feed_xml::Playlist {
+            id: PLAYLIST_ID.to_string(),
+            channel_id: CHANNEL_ID.to_string(),
+            title: "Fixture Playlist".to_string(),
+            author: "Fixture Channel".to_string(),
+            url: String::new(),
+            published: chrono::Utc::now(),
+            videos: None,
+        };
+        assert!(Feed::from_yt_playlist(playlist, page(), &profile, &state)
+            .await
+            .is_err());
+
+        std::fs::remove_dir_all(&data_dir).unwrap();
+    }
+
+    /// Has no details for any video, failing with `error` instead.
+    struct Unavailable(VpodError);
+
+    #[async_trait::async_trait]
+    impl MetadataSource for Unavailable {
+        async fn page(&self, _url: &str) -> Result<FeedPage> {
+            unimplemented!()
         }
+
+        async fn channel(&self, _id: &str) -> Result<yt_feed_xml::Channel> {
+            unimplemented!()
+        }
+
+        async fn playlist(&self, _id: &str) -> Result<yt_feed_xml::Playlist> {
+            unimplemented!()
+        }
+
+        async fn video_details(&self, _id: &str) -> Result<VideoDetails> {
+            Err(self.0.clone().into())
+        }
+    }
+
+    /// What is left of the first listed episode when its details fail with
+    /// `error`.
+    async fn length_added(test: &str, error: VpodError) -> Vec<Episode> {
+        let (mut state, data_dir) = test_state(
+            test,
+            r#"
+            [profiles.default]
+            sponsorblock = { mode = "off" }
+            "#,
+            &[],
+        )
+        .await;
+        state.source = std::sync::Arc::new(Unavailable(error));
+        let sponsorblock = state.config.profile(CHANNEL_ID).sponsorblock;
+
+        let mut eps = listed("merge-before");
+        eps.truncate(1);
+        let eps = add_episode_length(eps, &sponsorblock, &state).await;
+
+        std::fs::remove_dir_all(&data_dir).unwrap();
+        eps
+    }
+
+    #[tokio::test]
+    async fn test_members_only_video_is_left_out() {
+        assert!(length_added("members-only", VpodError::MembersOnly)
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_private_video_is_left_out() {
+        assert!(length_added("private", VpodError::VideoPrivate)
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_removed_video_is_left_out() {
+        assert!(length_added("removed", VpodError::VideoRemoved)
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_premiere_is_left_out() {
+        assert!(length_added("premiere", VpodError::NotYetAvailable)
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_transient_failure_keeps_estimated_length() {
+        let listed = listed("merge-before");
+        let eps = length_added("transient", VpodError::YoutubeDLError).await;
+        assert_eq!(eps.len(), 1);
+        assert_eq!(eps[0].duration_secs, listed[0].duration_secs);
     }
 }
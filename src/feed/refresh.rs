@@ -0,0 +1,368 @@
+use std::{
+    collections::HashMap,
+    hash::{DefaultHasher, Hasher},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use chrono::Utc;
+use futures::{
+    future::{BoxFuture, Shared},
+    FutureExt,
+};
+use tracing::Instrument;
+
+use super::{recheck_sponsor_segments, store::Freshness, update_feed, Feed, FeedPage, FeedType};
+use crate::error::{Report, Result};
+use crate::state::AppState;
+
+/// How far each feed's refreshes are moved early or late, as a share of the
+/// interval, so feeds added together don't keep refreshing together.
+const JITTER: f64 = 0.1;
+
+/// Most time between checks for feeds that are due.
+const MAX_TICK: Duration = Duration::from_secs(60);
+
+/// A refresh that is running, which anyone asking for the same feed waits on
+/// instead of starting another.
+type InFlight = Shared<BoxFuture<'static, Result<Feed, Arc<Report>>>>;
+
+/// Feeds being refreshed right now. This is the lock on each feed: a feed is
+/// only ever refreshed once at a time.
+#[derive(Clone, Default)]
+pub(crate) struct Refreshing(Arc<Mutex<HashMap<String, InFlight>>>);
+
+/// Held by the task refreshing a feed, and lets go of the feed when the
+/// task ends, however it ends.
+struct Refresh {
+    refreshing: Refreshing,
+    feed_id: String,
+}
+
+impl Drop for Refresh {
+    fn drop(&mut self) {
+        let mut feeds = self.refreshing.0.lock().unwrap_or_else(|e| e.into_inner());
+        feeds.remove(&self.feed_id);
+    }
+}
+
+/// Refresh every stored feed whose interval is up, for as long as the server
+/// runs. Feeds are refreshed one at a time.
+pub(crate) fn spawn_refresher(state: AppState) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval((state.refresh_interval / 10).min(MAX_TICK));
+        loop {
+            ticker.tick().await;
+            refresh_due(&state).await;
+        }
+    });
+}
+
+async fn refresh_due(state: &AppState) {
+    let feeds = match state.store.blocking(|store| store.freshness()).await {
+        Ok(feeds) => feeds,
+        Err(e) => {
+            tracing::error!("could not list feeds to refresh: {e:?}");
+            return;
+        }
+    };
+
+    for feed in feeds.into_iter().filter(|feed| is_due(feed, state)) {
+        // Failures are logged, the stored feed is served until one works.
+        let _ = refresh(&feed.feed_id, feed.feed_type, None, state).await;
+    }
+}
+
+/// Refresh the stored feed `feed_id` in the background if it is due.
+pub(super) async fn refresh_if_due(feed_id: &str, state: &AppState) {
+    let id = feed_id.to_owned();
+    let feed = match state
+        .store
+        .blocking(move |store| store.freshness_of(&id))
+        .await
+    {
+        Ok(Some(feed)) if is_due(&feed, state) => feed,
+        Ok(_) => return,
+        Err(e) => {
+            tracing::error!("could not check when {feed_id} was refreshed: {e:?}");
+            return;
+        }
+    };
+    // It runs on its own, nobody waits for it.
+    drop(start(feed_id, feed.feed_type, None, state));
+}
+
+/// Whether the last try at refreshing `feed` was an interval ago, give or
+/// take its jitter.
+fn is_due(feed: &Freshness, state: &AppState) -> bool {
+    let jitter = 1.0 + JITTER * (2.0 * jitter_unit(&feed.feed_id) - 1.0);
+    let interval = state.refresh_interval.mul_f64(jitter);
+    let since = Utc::now() - feed.attempted_at;
+    since.to_std().is_ok_and(|since| since >= interval)
+}
+
+/// A number between 0 and 1 that spreads feeds out, the same for `feed_id`
+/// every time so its refreshes stay an interval apart.
+fn jitter_unit(feed_id: &str) -> f64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(feed_id.as_bytes());
+    hasher.finish() as f64 / u64::MAX as f64
+}
+
+/// Fetch the feed `feed_id` from YouTube, fold it into what is stored and
+/// store the result. If that fails the stored feed is left as it was.
+///
+/// If `feed_id` is already being refreshed, this waits for that refresh and
+/// returns what it did. `page` is the feed's page, if it was already fetched.
+pub(super) async fn refresh(
+    feed_id: &str,
+    feed_type: FeedType,
+    page: Option<FeedPage>,
+    state: &AppState,
+) -> Result<Feed> {
+    start(feed_id, feed_type, page, state)
+        .await
+        .map_err(|e| e.copied())
+}
+
+/// Refresh `feed_id` in a task of its own, so it finishes even if whoever
+/// asked for it goes away, unless it is being refreshed already.
+fn start(feed_id: &str, feed_type: FeedType, page: Option<FeedPage>, state: &AppState) -> InFlight {
+    let mut feeds = state.refreshing.0.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(in_flight) = feeds.get(feed_id) {
+        return in_flight.clone();
+    }
+
+    // The task can't let go of the feed before it is marked as refreshing,
+    // `feeds` is locked until then.
+    let refresh_guard = Refresh {
+        refreshing: state.refreshing.clone(),
+        feed_id: feed_id.to_owned(),
+    };
+    let state = state.clone();
+    let task = tokio::spawn(
+        async move {
+            let refresh = refresh_guard;
+            run(&refresh.feed_id, feed_type, page, &state)
+                .await
+                .map_err(Arc::new)
+        }
+        .in_current_span(),
+    );
+    let in_flight = async move {
+        task.await.unwrap_or_else(|e| {
+            let e: Report = color_eyre::eyre::eyre!("refresh stopped: {e}").into();
+            Err(Arc::new(e))
+        })
+    }
+    .boxed()
+    .shared();
+
+    feeds.insert(feed_id.to_owned(), in_flight.clone());
+    in_flight
+}
+
+#[tracing::instrument(skip(page, state), fields(feed_type=format!("{feed_type}")))]
+async fn run(
+    feed_id: &str,
+    feed_type: FeedType,
+    page: Option<FeedPage>,
+    state: &AppState,
+) -> Result<Feed> {
+    let refreshed = build(feed_id, feed_type, page, state).await;
+    match &refreshed {
+        Ok(feed) => tracing::info!(
+            episodes = feed.episodes.as_ref().map_or(0, |eps| eps.len()),
+            "refreshed feed"
+        ),
+        Err(e) => {
+            tracing::warn!("could not refresh feed, the stored one is served: {e:?}");
+            let id = feed_id.to_owned();
+            let failed = state.store.blocking(move |store| store.refresh_failed(&id));
+            if let Err(e) = failed.await {
+                tracing::error!("could not record failed refresh: {e:?}");
+            }
+        }
+    }
+    refreshed
+}
+
+async fn build(
+    feed_id: &str,
+    feed_type: FeedType,
+    page: Option<FeedPage>,
+    state: &AppState,
+) -> Result<Feed> {
+    let settings = state.config.feed(feed_id);
+    let profile = state.config.profile(feed_id);
+    let sponsorblock = &profile.sponsorblock;
+    let feed_dir = state.feed_dir(feed_id);
+
+    let new_feed = Feed::new(feed_id, feed_type, page, &profile, state).await?;
+    let id = feed_id.to_owned();
+    let old_feed = state.store.blocking(move |store| store.load(&id)).await?;
+    let feed = match old_feed {
+        Some(old_feed) => update_feed(new_feed, old_feed),
+        None => new_feed,
+    };
+
+    let feed = if sponsorblock.is_enabled() {
+        recheck_sponsor_segments(
+            feed,
+            &feed_dir,
+            sponsorblock,
+            settings.sponsorblock_recheck(),
+            state,
+        )
+        .await
+    } else {
+        feed
+    };
+
+    // The normal speed feed is what is stored, every variant is rendered
+    // from it.
+    let id = feed_id.to_owned();
+    let downloads = state
+        .store
+        .blocking(move |store| store.downloads(&id))
+        .await?;
+    let feed = feed
+        .with_codec(profile.codec)
+        .with_downloaded_meta(&downloads);
+    let id = feed_id.to_owned();
+    state
+        .store
+        .blocking(move |store| {
+            store.save(&id, feed_type, &feed)?;
+            Ok(feed)
+        })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::Request, http::StatusCode};
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::state::test_state;
+
+    const CHANNEL_ID: &str = "UCfixture0000000000000000";
+
+    async fn state(test: &str) -> (AppState, std::path::PathBuf) {
+        let config = r#"
+            [profiles.default]
+            sponsorblock = { mode = "off" }
+        "#;
+        test_state(test, config, &[]).await
+    }
+
+    fn attempted_at(state: &AppState, feed_id: &str) -> chrono::DateTime<Utc> {
+        state
+            .store
+            .freshness_of(feed_id)
+            .unwrap()
+            .unwrap()
+            .attempted_at
+    }
+
+    #[test]
+    fn test_jitter_is_fixed_per_feed() {
+        let unit = jitter_unit(CHANNEL_ID);
+        assert!((0.0..=1.0).contains(&unit));
+        assert_eq!(jitter_unit(CHANNEL_ID), unit);
+        assert_ne!(jitter_unit("PLfixture0000000000000000000000000"), unit);
+    }
+
+    #[tokio::test]
+    async fn test_due_feeds_are_refreshed() {
+        let (state, data_dir) = state("refresh-due").await;
+        refresh(CHANNEL_ID, FeedType::Channel, None, &state)
+            .await
+            .unwrap();
+        let feed = state.store.freshness_of(CHANNEL_ID).unwrap().unwrap();
+        assert!(!is_due(&feed, &state));
+
+        state.store.backdate(CHANNEL_ID, chrono::Duration::hours(2));
+        let before = Utc::now();
+        refresh_due(&state).await;
+
+        assert!(attempted_at(&state, CHANNEL_ID) >= before);
+        let episodes = state.store.load(CHANNEL_ID).unwrap().unwrap().episodes;
+        assert_eq!(episodes.unwrap().len(), 2);
+
+        std::fs::remove_dir_all(&data_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_refreshes_share_one() {
+        let (state, data_dir) = state("refresh-shared").await;
+
+        let first = start(CHANNEL_ID, FeedType::Channel, None, &state);
+        let second = start(CHANNEL_ID, FeedType::Channel, None, &state);
+        assert!(first.ptr_eq(&second));
+        let (first, second) = tokio::join!(first, second);
+        assert_eq!(
+            first.unwrap().episodes.unwrap().len(),
+            second.unwrap().episodes.unwrap().len()
+        );
+
+        // Once it is done the feed can be refreshed again.
+        assert!(state.refreshing.0.lock().unwrap().is_empty());
+        let third = start(CHANNEL_ID, FeedType::Channel, None, &state);
+        assert!(third.await.is_ok());
+
+        // A failure is shared the same way.
+        let gone = "UCgone000000000000000000";
+        let (first, second) = tokio::join!(
+            refresh(gone, FeedType::Channel, None, &state),
+            refresh(gone, FeedType::Channel, None, &state)
+        );
+        assert!(first.is_err() && second.is_err());
+
+        std::fs::remove_dir_all(&data_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_stale_feed_is_served_when_refresh_fails() {
+        let (state, data_dir) = state("refresh-fails").await;
+        // A channel YouTube no longer has, stored while it still did.
+        let gone = "UCgone000000000000000000";
+        let feed = refresh(CHANNEL_ID, FeedType::Channel, None, &state)
+            .await
+            .unwrap();
+        state.store.save(gone, FeedType::Channel, &feed).unwrap();
+        state.store.backdate(gone, chrono::Duration::hours(2));
+        let before = Utc::now();
+
+        let app = crate::router(state.clone());
+        for _ in 0..2 {
+            let response = app
+                .clone()
+                .oneshot(
+                    Request::get(format!("/channel/{gone}"))
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let channel = rss::Channel::read_from(&body[..]).unwrap();
+            assert_eq!(channel.items().len(), 2);
+
+            // The first request set off a refresh, which fails.
+            for _ in 0..100 {
+                if attempted_at(&state, gone) >= before {
+                    break;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            assert!(attempted_at(&state, gone) >= before);
+        }
+
+        std::fs::remove_dir_all(&data_dir).unwrap();
+    }
+}
@@ -0,0 +1,392 @@
+use std::{path::PathBuf, sync::Arc};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{eyre, WrapErr};
+use scraper::Html;
+use serde::Deserialize;
+use url::Url;
+
+use super::{utils::FeedPage, VideoDetails};
+use crate::audio::{self, DownloadPool, Downloader};
+use crate::error::Result;
+use crate::upstream::Upstream;
+
+/// Where YouTube is, unless told otherwise.
+pub(crate) const YOUTUBE_URL: &str = "https://www.youtube.com/";
+
+/// Everything feeds are built from, apart from the episodes themselves.
+#[async_trait]
+pub(crate) trait MetadataSource: Send + Sync {
+    /// The channel or playlist page at `url`, e.g. `@handle`, `c/name` or
+    /// a channel's full URL.
+    async fn page(&self, url: &str) -> Result<FeedPage>;
+
+    /// The channel `id` with its latest videos.
+    async fn channel(&self, id: &str) -> Result<yt_feed_xml::Channel>;
+
+    /// The playlist `id` with its latest videos.
+    async fn playlist(&self, id: &str) -> Result<yt_feed_xml::Playlist>;
+
+    /// What is known about the video `id` before downloading it.
+    async fn video_details(&self, id: &str) -> Result<VideoDetails>;
+}
+
+/// YouTube's pages and Atom feeds, under `base`.
+///
+/// `base` is only ever something other than [`YOUTUBE_URL`] to point the
+/// server at a mock or recorded fixtures. Video details that can't be read
+/// off a watch page are asked of `downloader` instead, which runs on `pool`
+/// in `work_dir` and counts against `api`'s rate limit like a page would.
+pub(crate) struct YouTube {
+    base: Url,
+    api: Upstream,
+    downloader: Arc<dyn Downloader>,
+    pool: DownloadPool,
+    work_dir: PathBuf,
+}
+
+impl YouTube {
+    pub(crate) fn new(
+        base: Url,
+        api: Upstream,
+        downloader: Arc<dyn Downloader>,
+        pool: DownloadPool,
+        work_dir: PathBuf,
+    ) -> Self {
+        Self {
+            base,
+            api,
+            downloader,
+            pool,
+            work_dir,
+        }
+    }
+
+    fn url(&self, path: &str) -> Result<Url> {
+        Ok(self
+            .base
+            .join(path)
+            .wrap_err_with(|| format!("joining {path} to {}", self.base))?)
+    }
+
+    async fn get(&self, url: &str) -> Result<String> {
+        let resp = self.api.get(url).await.send().await?.error_for_status()?;
+        Ok(resp.text().await?)
+    }
+
+    async fn atom_feed(&self, query: &str) -> Result<AtomFeed> {
+        let url = self.url(&format!("feeds/videos.xml?{query}"))?;
+        let xml = self.get(url.as_str()).await?;
+        Ok(AtomFeed::parse(&xml).wrap_err_with(|| format!("parsing {url}"))?)
+    }
+}
+
+#[async_trait]
+impl MetadataSource for YouTube {
+    async fn page(&self, url: &str) -> Result<FeedPage> {
+        let html = self.get(self.url(url)?.as_str()).await?;
+        FeedPage::parse(&Html::parse_document(&html))
+    }
+
+    async fn channel(&self, id: &str) -> Result<yt_feed_xml::Channel> {
+        let feed = self.atom_feed(&format!("channel_id={id}")).await?;
+        Ok(yt_feed_xml::Channel {
+            id: feed.channel_id()?,
+            title: feed.title.value,
+            author: feed.author.name.value,
+            url: self.url(&format!("channel/{id}"))?.to_string(),
+            published: feed.published.value,
+            videos: Some(feed.entries.into_iter().map(Into::into).collect()),
+        })
+    }
+
+    async fn playlist(&self, id: &str) -> Result<yt_feed_xml::Playlist> {
+        let feed = self.atom_feed(&format!("playlist_id={id}")).await?;
+        Ok(yt_feed_xml::Playlist {
+            id: id.to_string(),
+            channel_id: feed.channel_id()?,
+            title: feed.title.value,
+            author: feed.author.name.value,
+            url: self.url(&format!("playlist?list={id}"))?.to_string(),
+            published: feed.published.value,
+            videos: Some(feed.entries.into_iter().map(Into::into).collect()),
+        })
+    }
+
+    async fn video_details(&self, id: &str) -> Result<VideoDetails> {
+        let from_page = async {
+            let page = self
+                .get(self.url(&format!("watch?v={id}"))?.as_str())
+                .await?;
+            VideoDetails::from_watch_page(&page)
+        };
+        let e = match from_page.await {
+            Ok(details) => return Ok(details),
+            Err(e) => e,
+        };
+        tracing::debug!(video_id = id, "asking yt-dlp, watch page failed: {e:?}");
+
+        let downloader = self.downloader.clone();
+        let work_dir = self.work_dir.clone();
+        let id = id.to_owned();
+        self.api.wait().await;
+        let json = self
+            .pool
+            .run(move || audio::video_info(&*downloader, &id, &work_dir))
+            .await?;
+        VideoDetails::from_dump_json(&json)
+    }
+}
+
+/// The parts of a YouTube Atom feed that feeds are built from.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AtomFeed {
+    channel_id: Option<Text>,
+    title: Text,
+    author: AtomAuthor,
+    published: Timestamp,
+    #[serde(rename = "entry", default)]
+    entries: Vec<AtomEntry>,
+}
+
+impl AtomFeed {
+    fn parse(xml: &str) -> Result<Self, serde_xml_rs::Error> {
+        let mut de = serde_xml_rs::Deserializer::new_from_reader(xml.as_bytes())
+            .non_contiguous_seq_elements(true);
+        AtomFeed::deserialize(&mut de)
+    }
+
+    /// Some feeds leave `yt:channelId` empty, their author's URL has it too.
+    fn channel_id(&self) -> Result<String> {
+        match &self.channel_id {
+            Some(id) if !id.value.is_empty() => Ok(id.value.clone()),
+            _ => Ok(self
+                .author
+                .uri
+                .value
+                .rsplit_once("/channel/")
+                .map(|(_, id)| id.to_string())
+                .ok_or(eyre!("feed has no channel ID"))?),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AtomEntry {
+    video_id: Text,
+    channel_id: Text,
+    title: Text,
+    link: AtomLink,
+    author: AtomAuthor,
+    published: Timestamp,
+    updated: Timestamp,
+    group: MediaGroup,
+}
+
+impl From<AtomEntry> for yt_feed_xml::Video {
+    fn from(entry: AtomEntry) -> Self {
+        Self {
+            id: entry.video_id.value,
+            title: entry.title.value,
+            author: entry.author.name.value,
+            description: entry
+                .group
+                .description
+                .map(|text| text.value)
+                .unwrap_or_default(),
+            thumbnail: entry.group.thumbnail.url,
+            published: entry.published.value,
+            updated: entry.updated.value,
+            url: entry.link.href,
+            author_url: entry.author.uri.value,
+            channel_id: entry.channel_id.value,
+            views: entry
+                .group
+                .community
+                .map(|community| community.statistics.views)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct AtomAuthor {
+    name: Text,
+    uri: Text,
+}
+
+#[derive(Debug, Deserialize)]
+struct AtomLink {
+    href: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct MediaGroup {
+    thumbnail: MediaThumbnail,
+    description: Option<Text>,
+    community: Option<MediaCommunity>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MediaThumbnail {
+    url: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct MediaCommunity {
+    statistics: MediaStatistics,
+}
+
+#[derive(Debug, Deserialize)]
+struct MediaStatistics {
+    views: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct Text {
+    #[serde(rename = "$value", default)]
+    value: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Timestamp {
+    #[serde(rename = "$value")]
+    value: DateTime<Utc>,
+}
+
+/// Serve `fixtures/youtube` the way YouTube would serve what was recorded in
+/// it, and return where. `/watch?v=abc` is answered from `watch/v=abc`.
+#[cfg(test)]
+pub(crate) async fn serve_fixtures() -> Url {
+    use axum::{http::StatusCode, http::Uri, response::IntoResponse};
+
+    async fn fixture(uri: Uri) -> axum::response::Response {
+        let mut path = format!(
+            "{}/fixtures/youtube{}",
+            env!("CARGO_MANIFEST_DIR"),
+            uri.path()
+        );
+        if let Some(query) = uri.query() {
+            path = format!("{path}/{query}");
+        }
+        match tokio::fs::read(&path).await {
+            Ok(body) => body.into_response(),
+            Err(_) => StatusCode::NOT_FOUND.into_response(),
+        }
+    }
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, axum::Router::new().fallback(fixture))
+            .await
+            .unwrap()
+    });
+
+    format!("http://{addr}/").parse().unwrap()
+}
+
+/// The videos in the Atom feed at `fixtures/{path}`.
+#[cfg(test)]
+pub(crate) fn fixture_videos(path: &str) -> Vec<yt_feed_xml::Video> {
+    let path = format!("{}/fixtures/{path}", env!("CARGO_MANIFEST_DIR"));
+    let xml = std::fs::read_to_string(path).unwrap();
+    let feed = AtomFeed::parse(&xml).unwrap();
+    feed.entries.into_iter().map(Into::into).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::FakeDownloader;
+    use crate::error::VpodError;
+
+    const CHANNEL_ID: &str = "UCfixture0000000000000000";
+
+    async fn youtube() -> YouTube {
+        youtube_with_pool(DownloadPool::new(1, 4)).await
+    }
+
+    async fn youtube_with_pool(pool: DownloadPool) -> YouTube {
+        let downloader =
+            FakeDownloader::default().failing("missing0000", "ERROR: Video unavailable");
+        YouTube::new(
+            serve_fixtures().await,
+            Upstream::new(reqwest::Client::new(), 0.0),
+            Arc::new(downloader),
+            pool,
+            std::env::temp_dir(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_channel_from_fixtures() {
+        let youtube = youtube().await;
+
+        let page = youtube.page("@fixture").await.unwrap();
+        assert_eq!(page.channel_id.as_deref(), Some(CHANNEL_ID));
+
+        let channel = youtube.channel(CHANNEL_ID).await.unwrap();
+        assert_eq!(channel.id, CHANNEL_ID);
+        assert_eq!(channel.title, "Fixture Channel");
+        let videos = channel.videos.unwrap();
+        assert_eq!(
+            videos.iter().map(|v| v.id.as_str()).collect::<Vec<_>>(),
+            ["fixtureLive", "fixtureShrt", "fixtureVid2", "fixtureVid1"]
+        );
+        assert_eq!(videos[3].description, "About The first episode.");
+        assert_eq!(videos[3].views, 345);
+
+        let details = youtube.video_details("fixtureVid1").await.unwrap();
+        assert_eq!(details.duration_secs, Some(754));
+        // Falls back to the downloader, which fails like yt-dlp would.
+        let e = youtube.video_details("missing0000").await.unwrap_err();
+        assert!(matches!(e.vpod_error(), Some(VpodError::VideoRemoved)));
+        assert_eq!(youtube.page(&channel.url).await.unwrap(), page);
+    }
+
+    #[tokio::test]
+    async fn test_playlist_from_fixtures() {
+        let youtube = youtube().await;
+
+        let playlist = youtube
+            .playlist("PLfixture0000000000000000000000000")
+            .await
+            .unwrap();
+        assert_eq!(playlist.channel_id, CHANNEL_ID);
+        assert_eq!(playlist.videos.unwrap().len(), 2);
+        assert_eq!(
+            youtube.page(&playlist.url).await.unwrap().description,
+            "A playlist recorded for offline tests."
+        );
+    }
+
+    #[tokio::test]
+    async fn test_yt_dlp_fallback_waits_for_the_download_pool() {
+        let pool = DownloadPool::new(1, 1);
+        let youtube = youtube_with_pool(pool.clone()).await;
+        let (release, blocked) = std::sync::mpsc::channel::<()>();
+
+        // Occupy the only worker, then the only queue slot.
+        let busy = pool
+            .submit(move || {
+                blocked.recv().unwrap();
+                Ok(())
+            })
+            .unwrap();
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        let queued = pool.submit(|| Ok(())).unwrap();
+
+        // The watch page is there, so yt-dlp isn't needed.
+        assert!(youtube.video_details("fixtureVid1").await.is_ok());
+        let e = youtube.video_details("missing0000").await.unwrap_err();
+        assert!(matches!(e.vpod_error(), Some(VpodError::DownloadQueueFull)));
+
+        release.send(()).unwrap();
+        assert!(busy.await.is_ok());
+        assert!(queued.await.is_ok());
+    }
+}
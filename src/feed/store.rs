@@ -0,0 +1,798 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::BufReader,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Timelike, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+
+use super::{episode::Episode, Feed, FeedType};
+use crate::audio::{EpisodeFile, EpisodeMeta};
+use crate::config::FeedSettings;
+use crate::error::Result;
+
+/// Schema changes, applied in order. The database's `user_version` is how
+/// many of them it has had.
+const MIGRATIONS: &[&str] = &[
+    // 1: feeds and their episodes, in feed order.
+    "CREATE TABLE feeds (
+        id TEXT PRIMARY KEY,
+        feed_type TEXT NOT NULL,
+        title TEXT NOT NULL,
+        author TEXT NOT NULL,
+        description TEXT NOT NULL,
+        link TEXT NOT NULL,
+        image TEXT NOT NULL,
+        updated_at TEXT NOT NULL
+    );
+    CREATE TABLE episodes (
+        feed_id TEXT NOT NULL REFERENCES feeds (id) ON DELETE CASCADE,
+        video_id TEXT NOT NULL,
+        position INTEGER NOT NULL,
+        number INTEGER,
+        title TEXT NOT NULL,
+        url TEXT NOT NULL,
+        duration_secs INTEGER NOT NULL,
+        size_bytes INTEGER,
+        author TEXT NOT NULL,
+        published TEXT NOT NULL,
+        link TEXT NOT NULL,
+        description TEXT NOT NULL,
+        image TEXT,
+        explicit INTEGER NOT NULL,
+        categories TEXT NOT NULL,
+        PRIMARY KEY (feed_id, video_id)
+    );",
+    // 2: when refreshes were last tried, and which channel each path like
+    // `@handle` leads to, so neither needs YouTube to answer a request.
+    "ALTER TABLE feeds ADD COLUMN attempted_at TEXT;
+    CREATE TABLE feed_paths (
+        path TEXT PRIMARY KEY,
+        feed_id TEXT NOT NULL REFERENCES feeds (id) ON DELETE CASCADE
+    );",
+    // 3: the last response for each feed and tempo, so clients can be told
+    // when it last changed.
+    "CREATE TABLE renders (
+        feed_id TEXT NOT NULL REFERENCES feeds (id) ON DELETE CASCADE,
+        variant TEXT NOT NULL,
+        etag TEXT NOT NULL,
+        modified_at TEXT NOT NULL,
+        PRIMARY KEY (feed_id, variant)
+    );",
+    // 4: what is known about each downloaded file, including post-processing
+    // that failed so it isn't retried on every request, and the settings of
+    // each feed. Files are downloaded for feeds that may not be stored yet,
+    // so neither refers to `feeds`.
+    "CREATE TABLE downloads (
+        feed_id TEXT NOT NULL,
+        file_name TEXT NOT NULL,
+        size_bytes INTEGER,
+        duration_secs INTEGER,
+        loudness_lufs REAL,
+        silence_trimmed TEXT,
+        sponsorblock_segments TEXT,
+        removed_spans TEXT,
+        failed_loudness_lufs REAL,
+        failed_silence_trim TEXT,
+        PRIMARY KEY (feed_id, file_name)
+    );
+    CREATE TABLE feed_settings (
+        feed_id TEXT PRIMARY KEY,
+        settings TEXT NOT NULL
+    );",
+];
+
+const EPISODE_COLUMNS: &str = "video_id, number, title, url, duration_secs, size_bytes, author, \
+     published, link, description, image, explicit, categories";
+
+const DOWNLOAD_COLUMNS: &str = "file_name, size_bytes, duration_secs, loudness_lufs, \
+     silence_trimmed, sponsorblock_segments, failed_loudness_lufs, failed_silence_trim, \
+     removed_spans";
+
+/// When a stored feed was last refreshed.
+#[derive(Debug, Clone)]
+pub(super) struct Freshness {
+    pub(super) feed_id: String,
+    pub(super) feed_type: FeedType,
+    /// When refreshing it was last tried, successfully or not.
+    pub(super) attempted_at: DateTime<Utc>,
+}
+
+/// Feeds, their episodes, what is known about their downloads and their
+/// settings, kept in SQLite between requests.
+///
+/// Episodes keep their real size and duration once downloaded, so feeds and
+/// HEAD requests can be answered without YouTube.
+///
+/// Queries block, so async code runs them through [`Store::blocking`].
+#[derive(Clone, Debug)]
+pub(crate) struct Store {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl Store {
+    /// Open the database at `path`, creating and migrating it as needed. A
+    /// new database starts out with the XML feeds found under `data_dir`.
+    pub(crate) fn open(path: &Path, data_dir: &Path) -> Result<Self> {
+        let store = Self::from_connection(Connection::open(path)?)?;
+        let prior = store.migrate()?;
+        if prior == 0 {
+            store.import_xml(data_dir);
+        }
+        Ok(store)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn in_memory() -> Result<Self> {
+        let store = Self::from_connection(Connection::open_in_memory()?)?;
+        store.migrate()?;
+        Ok(store)
+    }
+
+    fn from_connection(conn: Connection) -> Result<Self> {
+        conn.pragma_update(None, "foreign_keys", true)?;
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    fn conn(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Run `f` on the blocking thread pool, so waiting for the connection or
+    /// the disk doesn't hold up the async runtime.
+    pub(crate) async fn blocking<T, F>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(&Store) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let store = self.clone();
+        tokio::task::spawn_blocking(move || f(&store)).await?
+    }
+
+    /// Apply the migrations the database hasn't had yet, and return the
+    /// version it was at before.
+    fn migrate(&self) -> Result<usize> {
+        let mut conn = self.conn();
+        let version: usize = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            let tx = conn.transaction()?;
+            tx.execute_batch(migration)?;
+            tx.pragma_update(None, "user_version", i + 1)?;
+            tx.commit()?;
+            tracing::info!(version = i + 1, "migrated store");
+        }
+
+        Ok(version)
+    }
+
+    /// The stored feed `feed_id`, if it has been built before.
+    pub(super) fn load(&self, feed_id: &str) -> Result<Option<Feed>> {
+        let conn = self.conn();
+        let feed = conn
+            .query_row(
+                "SELECT title, author, description, link, image FROM feeds WHERE id = ?1",
+                [feed_id],
+                |row| {
+                    Ok(Feed {
+                        title: row.get(0)?,
+                        author: row.get(1)?,
+                        description: row.get(2)?,
+                        link: row.get(3)?,
+                        image: row.get(4)?,
+                        episodes: None,
+                    })
+                },
+            )
+            .optional()?;
+        let Some(feed) = feed else {
+            return Ok(None);
+        };
+
+        let mut statement = conn.prepare(&format!(
+            "SELECT {EPISODE_COLUMNS} FROM episodes WHERE feed_id = ?1 ORDER BY position"
+        ))?;
+        let episodes = statement
+            .query_map([feed_id], episode_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        Ok(Some(Feed {
+            episodes: Some(episodes),
+            ..feed
+        }))
+    }
+
+    /// Store `feed` as `feed_id`, replacing whatever was stored for it. It is
+    /// one transaction, so readers get the old feed or the new one, never
+    /// part of either.
+    pub(super) fn save(&self, feed_id: &str, feed_type: FeedType, feed: &Feed) -> Result<()> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+
+        tx.execute(
+            "INSERT INTO feeds
+                 (id, feed_type, title, author, description, link, image, updated_at, attempted_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?8)
+             ON CONFLICT (id) DO UPDATE SET
+                 feed_type = excluded.feed_type,
+                 title = excluded.title,
+                 author = excluded.author,
+                 description = excluded.description,
+                 link = excluded.link,
+                 image = excluded.image,
+                 updated_at = excluded.updated_at,
+                 attempted_at = excluded.attempted_at",
+            params![
+                feed_id,
+                feed_type.to_string(),
+                feed.title,
+                feed.author,
+                feed.description,
+                feed.link,
+                feed.image,
+                chrono::Utc::now().to_rfc3339(),
+            ],
+        )?;
+        tx.execute("DELETE FROM episodes WHERE feed_id = ?1", [feed_id])?;
+
+        {
+            let mut insert = tx.prepare(&format!(
+                "INSERT INTO episodes (feed_id, position, {EPISODE_COLUMNS})
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)"
+            ))?;
+            for (position, ep) in feed.episodes.iter().flatten().enumerate() {
+                insert.execute(params![
+                    feed_id,
+                    position,
+                    ep.id.value(),
+                    ep.episode,
+                    ep.title,
+                    ep.url,
+                    ep.duration_secs,
+                    ep.size_bytes,
+                    ep.author,
+                    ep.date,
+                    ep.link,
+                    ep.description,
+                    ep.image,
+                    ep.explicit,
+                    serde_json::to_string(&ep.categories)?,
+                ])?;
+            }
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// How fresh every stored feed is.
+    pub(super) fn freshness(&self) -> Result<Vec<Freshness>> {
+        let conn = self.conn();
+        let mut statement = conn.prepare(
+            "SELECT id, feed_type, updated_at, attempted_at FROM feeds ORDER BY updated_at",
+        )?;
+        let feeds = statement
+            .query_map([], freshness_from_row)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        Ok(feeds.into_iter().flatten().collect())
+    }
+
+    /// How fresh the stored feed `feed_id` is, if there is one.
+    pub(super) fn freshness_of(&self, feed_id: &str) -> Result<Option<Freshness>> {
+        let freshness = self
+            .conn()
+            .query_row(
+                "SELECT id, feed_type, updated_at, attempted_at FROM feeds WHERE id = ?1",
+                [feed_id],
+                freshness_from_row,
+            )
+            .optional()?;
+        Ok(freshness.flatten())
+    }
+
+    /// Note that refreshing `feed_id` was tried just now and failed.
+    pub(super) fn refresh_failed(&self, feed_id: &str) -> Result<()> {
+        self.conn().execute(
+            "UPDATE feeds SET attempted_at = ?2 WHERE id = ?1",
+            params![feed_id, Utc::now().to_rfc3339()],
+        )?;
+        Ok(())
+    }
+
+    /// The feed a path like `@handle` or `c/name` was found to lead to.
+    pub(super) fn feed_at(&self, path: &str) -> Result<Option<String>> {
+        Ok(self
+            .conn()
+            .query_row(
+                "SELECT feed_id FROM feed_paths WHERE path = ?1",
+                [path],
+                |row| row.get(0),
+            )
+            .optional()?)
+    }
+
+    /// Remember that `path` leads to the stored feed `feed_id`.
+    pub(super) fn save_path(&self, path: &str, feed_id: &str) -> Result<()> {
+        self.conn().execute(
+            "INSERT INTO feed_paths (path, feed_id) VALUES (?1, ?2)
+             ON CONFLICT (path) DO UPDATE SET feed_id = excluded.feed_id",
+            [path, feed_id],
+        )?;
+        Ok(())
+    }
+
+    /// Record that the `variant` of `feed_id` was rendered with `etag`, and
+    /// return when it was first rendered like that.
+    pub(super) fn rendered(
+        &self,
+        feed_id: &str,
+        variant: &str,
+        etag: &str,
+    ) -> Result<DateTime<Utc>> {
+        let conn = self.conn();
+        let last: Option<(String, String)> = conn
+            .query_row(
+                "SELECT etag, modified_at FROM renders WHERE feed_id = ?1 AND variant = ?2",
+                [feed_id, variant],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        if let Some((last_etag, modified_at)) = last {
+            let modified_at = DateTime::parse_from_rfc3339(&modified_at);
+            if let (true, Ok(modified_at)) = (last_etag == etag, modified_at) {
+                return Ok(modified_at.with_timezone(&Utc));
+            }
+        }
+
+        // Whole seconds, which is all Last-Modified can say.
+        let now = Utc::now().with_nanosecond(0).unwrap_or_else(Utc::now);
+        conn.execute(
+            "INSERT INTO renders (feed_id, variant, etag, modified_at) VALUES (?1, ?2, ?3, ?4)
+             ON CONFLICT (feed_id, variant) DO UPDATE SET
+                 etag = excluded.etag,
+                 modified_at = excluded.modified_at",
+            params![feed_id, variant, etag, now.to_rfc3339()],
+        )?;
+        Ok(now)
+    }
+
+    /// Pretend `feed_id` was last refreshed and tried `age` ago.
+    #[cfg(test)]
+    pub(super) fn backdate(&self, feed_id: &str, age: chrono::Duration) {
+        let then = (Utc::now() - age).to_rfc3339();
+        self.conn()
+            .execute(
+                "UPDATE feeds SET updated_at = ?2, attempted_at = ?2 WHERE id = ?1",
+                [feed_id, &then],
+            )
+            .unwrap();
+    }
+
+    /// Enclosure length of `file_name` as last rendered in the feed
+    /// `feed_id`, if it is one of its episodes.
+    pub(crate) fn enclosure_length(&self, feed_id: &str, file_name: &str) -> Result<Option<u64>> {
+        let Some(file) = EpisodeFile::parse(file_name) else {
+            return Ok(None);
+        };
+
+        let episode = self
+            .conn()
+            .query_row(
+                &format!(
+                    "SELECT {EPISODE_COLUMNS} FROM episodes WHERE feed_id = ?1 AND video_id = ?2"
+                ),
+                [feed_id, file.ep_id],
+                episode_from_row,
+            )
+            .optional()?;
+
+        Ok(episode
+            .filter(|ep| ep.file_name() == file_name)
+            .map(|ep| ep.enclosure_length()))
+    }
+
+    /// What is known about the downloaded `file_name` of `feed_id`, if it
+    /// has been downloaded.
+    pub(crate) fn download(&self, feed_id: &str, file_name: &str) -> Result<Option<EpisodeMeta>> {
+        Ok(self
+            .conn()
+            .query_row(
+                &format!(
+                    "SELECT {DOWNLOAD_COLUMNS} FROM downloads WHERE feed_id = ?1 AND file_name = ?2"
+                ),
+                [feed_id, file_name],
+                download_from_row,
+            )
+            .optional()?
+            .map(|(_, meta)| meta))
+    }
+
+    /// What is known about every downloaded file of `feed_id`, by file name.
+    pub(crate) fn downloads(&self, feed_id: &str) -> Result<HashMap<String, EpisodeMeta>> {
+        let conn = self.conn();
+        let mut statement = conn.prepare(&format!(
+            "SELECT {DOWNLOAD_COLUMNS} FROM downloads WHERE feed_id = ?1"
+        ))?;
+        let downloads = statement
+            .query_map([feed_id], download_from_row)?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(downloads)
+    }
+
+    /// Store `meta` for the downloaded `file_name` of `feed_id`, replacing
+    /// whatever was stored for it.
+    pub(crate) fn save_download(
+        &self,
+        feed_id: &str,
+        file_name: &str,
+        meta: &EpisodeMeta,
+    ) -> Result<()> {
+        self.conn().execute(
+            &format!(
+                "INSERT OR REPLACE INTO downloads (feed_id, {DOWNLOAD_COLUMNS})
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)"
+            ),
+            params![
+                feed_id,
+                file_name,
+                meta.size_bytes,
+                meta.duration_secs,
+                meta.loudness_lufs,
+                optional_json(&meta.silence_trimmed)?,
+                optional_json(&meta.sponsorblock_segments)?,
+                meta.failed_loudness_lufs,
+                optional_json(&meta.failed_silence_trim)?,
+                optional_json(&meta.removed_spans)?,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Forget the downloaded `file_name` of `feed_id`, once it is removed.
+    pub(crate) fn forget_download(&self, feed_id: &str, file_name: &str) -> Result<()> {
+        self.conn().execute(
+            "DELETE FROM downloads WHERE feed_id = ?1 AND file_name = ?2",
+            [feed_id, file_name],
+        )?;
+        Ok(())
+    }
+
+    /// The stored settings of every feed that has any.
+    #[cfg(test)]
+    pub(crate) fn feed_settings(&self) -> Result<HashMap<String, FeedSettings>> {
+        let conn = self.conn();
+        let mut statement = conn.prepare("SELECT feed_id, settings FROM feed_settings")?;
+        let rows = statement
+            .query_map([], |row| {
+                Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        rows.into_iter()
+            .map(|(feed_id, settings)| Ok((feed_id, serde_json::from_str(&settings)?)))
+            .collect()
+    }
+
+    /// Store `settings` as the settings of every feed, dropping those of
+    /// feeds it doesn't mention.
+    pub(crate) fn replace_feed_settings(
+        &self,
+        settings: &HashMap<String, FeedSettings>,
+    ) -> Result<()> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM feed_settings", [])?;
+        {
+            let mut insert =
+                tx.prepare("INSERT INTO feed_settings (feed_id, settings) VALUES (?1, ?2)")?;
+            for (feed_id, settings) in settings {
+                insert.execute([feed_id, &serde_json::to_string(settings)?])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Store the feeds earlier versions wrote as `{type}-{id}.xml` into each
+    /// feed's directory under `data_dir`. Files that can't be read are
+    /// skipped, and left where they are either way.
+    fn import_xml(&self, data_dir: &Path) {
+        let Ok(dirs) = fs::read_dir(data_dir) else {
+            return;
+        };
+
+        for dir in dirs.flatten() {
+            let feed_id = dir.file_name().to_string_lossy().into_owned();
+            for feed_type in [FeedType::Channel, FeedType::Playlist] {
+                let path = dir.path().join(format!("{feed_type}-{feed_id}.xml"));
+                if !path.is_file() {
+                    continue;
+                }
+
+                let imported = read_xml(&path)
+                    .and_then(|feed| self.save(&feed_id, feed_type, &feed).map(|_| feed));
+                match imported {
+                    Ok(feed) => tracing::info!(
+                        feed_id,
+                        episodes = feed.episodes.map_or(0, |eps| eps.len()),
+                        "imported {}",
+                        path.display()
+                    ),
+                    Err(e) => tracing::warn!("could not import {}: {e:?}", path.display()),
+                }
+            }
+        }
+    }
+}
+
+fn read_xml(path: &Path) -> Result<Feed> {
+    let file = fs::File::open(path)?;
+    let channel = rss::Channel::read_from(BufReader::new(file))?;
+    Feed::try_from(channel)
+}
+
+/// `None` for rows this version can't make sense of.
+fn freshness_from_row(row: &Row) -> rusqlite::Result<Option<Freshness>> {
+    let time = |text: Option<String>| {
+        DateTime::parse_from_rfc3339(&text?)
+            .ok()
+            .map(|time| time.with_timezone(&Utc))
+    };
+    let feed_type: String = row.get(1)?;
+    let Ok(feed_type) = feed_type.parse() else {
+        return Ok(None);
+    };
+    let Some(refreshed_at) = time(row.get(2)?) else {
+        return Ok(None);
+    };
+
+    Ok(Some(Freshness {
+        feed_id: row.get(0)?,
+        feed_type,
+        // Feeds stored before attempts were recorded were last tried when
+        // they were last refreshed.
+        attempted_at: time(row.get(3)?).unwrap_or(refreshed_at),
+    }))
+}
+
+fn optional_json<T: serde::Serialize>(value: &Option<T>) -> serde_json::Result<Option<String>> {
+    value.as_ref().map(serde_json::to_string).transpose()
+}
+
+/// The JSON in column `i` of `row`, `None` if there is none this version can
+/// read.
+fn json_column<T: serde::de::DeserializeOwned>(row: &Row, i: usize) -> rusqlite::Result<Option<T>> {
+    let text: Option<String> = row.get(i)?;
+    Ok(text.and_then(|text| serde_json::from_str(&text).ok()))
+}
+
+fn download_from_row(row: &Row) -> rusqlite::Result<(String, EpisodeMeta)> {
+    Ok((
+        row.get(0)?,
+        EpisodeMeta {
+            size_bytes: row.get(1)?,
+            duration_secs: row.get(2)?,
+            loudness_lufs: row.get(3)?,
+            silence_trimmed: json_column(row, 4)?,
+            sponsorblock_segments: json_column(row, 5)?,
+            failed_loudness_lufs: row.get(6)?,
+            failed_silence_trim: json_column(row, 7)?,
+            removed_spans: json_column(row, 8)?,
+        },
+    ))
+}
+
+fn episode_from_row(row: &Row) -> rusqlite::Result<Episode> {
+    let categories: String = row.get(12)?;
+    let duration_secs = row.get(4)?;
+
+    Ok(Episode {
+        id: rss::GuidBuilder::default()
+            .value(row.get::<_, String>(0)?)
+            .build(),
+        episode: row.get(1)?,
+        title: row.get(2)?,
+        url: row.get(3)?,
+        duration_str: String::new(),
+        duration_secs,
+        size_bytes: row.get(5)?,
+        transcript_language: None,
+        chapters: true,
+        author: row.get(6)?,
+        date: row.get(7)?,
+        link: row.get(8)?,
+        description: row.get(9)?,
+        image: row.get(10)?,
+        explicit: row.get(11)?,
+        categories: serde_json::from_str(&categories).unwrap_or_default(),
+    }
+    .set_length(duration_secs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn episode(id: &str, number: u32) -> Episode {
+        Episode {
+            id: rss::GuidBuilder::default().value(id).build(),
+            url: format!("http://localhost/ep/feed/{id}.m4a"),
+            episode: Some(number),
+            title: format!("Episode {number}"),
+            duration_str: String::new(),
+            duration_secs: 0,
+            size_bytes: None,
+            transcript_language: None,
+            chapters: true,
+            author: "Fixture Channel".to_string(),
+            date: "Mon, 01 Jan 2024 12:00:00 +0000".to_string(),
+            link: format!("https://www.youtube.com/watch?v={id}"),
+            description: String::new(),
+            image: None,
+            explicit: false,
+            categories: vec!["Gaming".to_string()],
+        }
+        .set_length(754)
+    }
+
+    fn feed(episodes: Vec<Episode>) -> Feed {
+        Feed {
+            image: "https://yt3.googleusercontent.com/fixture=s900".to_string(),
+            title: "Fixture Channel".to_string(),
+            author: "Fixture Channel".to_string(),
+            description: "A channel recorded for offline tests.".to_string(),
+            link: "https://www.youtube.com/channel/feed".to_string(),
+            episodes: Some(episodes),
+        }
+    }
+
+    #[test]
+    fn test_feed_round_trips() {
+        let store = Store::in_memory().unwrap();
+        assert!(store.load("feed").unwrap().is_none());
+
+        let downloaded = episode("abc00000000", 0).set_size(Some(1234));
+        let saved = feed(vec![downloaded, episode("def00000000", 1)]);
+        store.save("feed", FeedType::Channel, &saved).unwrap();
+
+        let loaded = store.load("feed").unwrap().unwrap();
+        assert_eq!(loaded.episodes, saved.episodes);
+        assert_eq!(loaded.title, saved.title);
+
+        assert_eq!(
+            store.enclosure_length("feed", "abc00000000.m4a").unwrap(),
+            Some(1234)
+        );
+        assert!(store
+            .enclosure_length("feed", "abc00000000.opus")
+            .unwrap()
+            .is_none());
+        assert!(store
+            .enclosure_length("other", "abc00000000.m4a")
+            .unwrap()
+            .is_none());
+
+        // Saving again replaces the episodes instead of adding to them.
+        store
+            .save(
+                "feed",
+                FeedType::Channel,
+                &feed(vec![episode("def00000000", 0)]),
+            )
+            .unwrap();
+        assert_eq!(
+            store.load("feed").unwrap().unwrap().episodes.unwrap().len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_xml_is_imported_once() {
+        let data_dir =
+            std::env::temp_dir().join(format!("vpod-store-import-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&data_dir);
+        fs::create_dir_all(data_dir.join("good")).unwrap();
+        fs::create_dir_all(data_dir.join("broken")).unwrap();
+
+        let mut channel = rss::Channel::from(feed(vec![
+            episode("abc00000000", 0),
+            episode("def00000000", 1),
+        ]));
+        // An item an older version or a hand edit left without a duration.
+        let mut items = channel.items().to_vec();
+        items[1].set_itunes_ext(None);
+        channel.set_items(items);
+        let xml = fs::File::create(data_dir.join("good/channel-good.xml")).unwrap();
+        channel.write_to(xml).unwrap();
+        fs::write(data_dir.join("broken/channel-broken.xml"), "<rss>").unwrap();
+
+        let db = data_dir.join("vpod.db");
+        let store = Store::open(&db, &data_dir).unwrap();
+        let imported = store.load("good").unwrap().unwrap();
+        assert_eq!(imported.episodes, Some(vec![episode("abc00000000", 0)]));
+        assert!(store.load("broken").unwrap().is_none());
+
+        // Only a new database imports anything.
+        store
+            .save("good", FeedType::Channel, &feed(vec![]))
+            .unwrap();
+        drop(store);
+        let store = Store::open(&db, &data_dir).unwrap();
+        assert_eq!(store.load("good").unwrap().unwrap().episodes, Some(vec![]));
+
+        fs::remove_dir_all(&data_dir).unwrap();
+    }
+
+    #[test]
+    fn test_downloads_round_trip() {
+        let store = Store::in_memory().unwrap();
+        assert!(store.download("feed", "abc00000000.m4a").unwrap().is_none());
+
+        let meta = EpisodeMeta {
+            sponsorblock_segments: Some(vec!["uuid".to_string()]),
+            removed_spans: Some(vec![(10.0, 40.5)]),
+            size_bytes: Some(1234),
+            duration_secs: Some(754),
+            loudness_lufs: Some(-16.0),
+            silence_trimmed: Some(toml::from_str("threshold_db = -40").unwrap()),
+            ..EpisodeMeta::default()
+        };
+        store
+            .save_download("feed", "abc00000000.m4a", &meta)
+            .unwrap();
+        store
+            .save_download("feed", "def00000000.m4a", &EpisodeMeta::default())
+            .unwrap();
+
+        let loaded = store.download("feed", "abc00000000.m4a").unwrap().unwrap();
+        assert_eq!(loaded.sponsorblock_segments, meta.sponsorblock_segments);
+        assert_eq!(loaded.removed_spans, meta.removed_spans);
+        assert_eq!(loaded.size_bytes, Some(1234));
+        assert_eq!(loaded.duration_secs, Some(754));
+        assert_eq!(loaded.loudness_lufs, Some(-16.0));
+        assert_eq!(loaded.silence_trimmed, meta.silence_trimmed);
+        assert_eq!(store.downloads("feed").unwrap().len(), 2);
+        assert!(store.downloads("other").unwrap().is_empty());
+
+        store.forget_download("feed", "abc00000000.m4a").unwrap();
+        assert!(store.download("feed", "abc00000000.m4a").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_feed_settings_are_replaced() {
+        let store = Store::in_memory().unwrap();
+        let settings = |toml: &str| -> FeedSettings { toml::from_str(toml).unwrap() };
+
+        // Left over from a version that stored settings this one can't read.
+        store
+            .conn()
+            .execute(
+                "INSERT INTO feed_settings (feed_id, settings) VALUES ('old', '{\"x\": 1}')",
+                [],
+            )
+            .unwrap();
+        store
+            .replace_feed_settings(&HashMap::from([
+                ("a".to_string(), settings("tempos = [1.5]")),
+                ("b".to_string(), settings("embargo_hours = 6")),
+            ]))
+            .unwrap();
+        store
+            .replace_feed_settings(&HashMap::from([(
+                "a".to_string(),
+                settings("transcript_language = \"en\""),
+            )]))
+            .unwrap();
+
+        let stored = store.feed_settings().unwrap();
+        assert_eq!(stored.len(), 1);
+        assert!(stored["a"].tempos.is_empty());
+        assert_eq!(stored["a"].transcript_language.as_deref(), Some("en"));
+    }
+}
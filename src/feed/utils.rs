@@ -2,81 +2,109 @@ use crate::error::Result;
 use color_eyre::eyre::eyre;
 use scraper::{Html, Selector};
 
-async fn get_html(url: &str) -> Result<Html> {
-    let resp = reqwest::get(url).await?;
-    let text = resp.text().await?;
-    Ok(Html::parse_document(&text))
+/// What feeds take from a channel or playlist page.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct FeedPage {
+    /// `None` if the page has no canonical link to take it from.
+    pub(crate) channel_id: Option<String>,
+    pub(crate) image: String,
+    /// Empty if the page has none.
+    pub(crate) description: String,
 }
 
-pub async fn get_channel_id(url: &str) -> Result<String> {
-    let document = get_html(url).await.unwrap();
+impl FeedPage {
+    pub(crate) fn parse(document: &Html) -> Result<Self> {
+        Ok(Self {
+            channel_id: get_channel_id(document).ok(),
+            image: get_feed_image(document)?,
+            description: get_feed_description(document),
+        })
+    }
+}
+
+pub fn get_channel_id(document: &Html) -> Result<String> {
     let selector = Selector::parse(r#"body > link[rel="canonical"]"#).unwrap();
     let link = document
         .select(&selector)
         .next()
-        .map(|el| el.value().attr("href").unwrap())
+        .and_then(|el| el.value().attr("href"))
         .ok_or(eyre!("Could not find canonical link"))?;
 
     let id = link
         .split('/')
-        .rev()
-        .next()
+        .next_back()
         .ok_or(eyre!("Canonical link had no href"))?
         .to_string();
 
     Ok(id)
 }
 
-pub async fn get_feed_image(url: &str) -> Result<String> {
-    let document = get_html(url).await?;
+pub fn get_feed_image(document: &Html) -> Result<String> {
     let selector = Selector::parse(r#"body > meta[property="og:image"]"#).unwrap();
     let link = document
         .select(&selector)
         .next()
-        .map(|el| el.value().attr("content").unwrap())
-        .expect("could not find canonical link for channel");
+        .and_then(|el| el.value().attr("content"))
+        .ok_or(eyre!("Could not find image for feed"))?;
 
     Ok(link.to_string())
 }
 
-pub async fn get_feed_description(url: &str) -> Result<String> {
-    let document = get_html(url).await?;
+pub fn get_feed_description(document: &Html) -> String {
     let selector = Selector::parse(r#"body > meta[property="og:description"]"#).unwrap();
     let description = document
         .select(&selector)
         .next()
-        .map(|el| el.value().attr("content").unwrap());
+        .and_then(|el| el.value().attr("content"));
 
-    let description = match description {
+    match description {
         Some(description) => description.to_owned(),
         None => String::new(),
-    };
-
-    Ok(description)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    #[tokio::test]
-    async fn test_grim_beard_id() {
+    fn fixture(path: &str) -> Html {
+        let path = format!("{}/fixtures/youtube/{path}", env!("CARGO_MANIFEST_DIR"));
+        Html::parse_document(&std::fs::read_to_string(path).unwrap())
+    }
+
+    #[test]
+    fn test_grim_beard_id() {
         let grim_beard = "UCNmv1Cmjm3Hk8Vc9kIgv0AQ";
+        assert_eq!(get_channel_id(&fixture("c/GrimBeard")).unwrap(), grim_beard);
+    }
+    #[test]
+    fn test_vihart_id() {
+        let vihart = "UCOGeU-1Fig3rrDjhm9Zs_wg";
+        assert_eq!(get_channel_id(&fixture("user/vihart")).unwrap(), vihart);
+    }
+
+    /// Checks the real page still has what the fixtures are recorded with.
+    /// Needs the network, run it with `cargo test -- --ignored`.
+    #[tokio::test]
+    #[ignore]
+    async fn test_grim_beard_id_live() {
+        let url = format!("{}c/GrimBeard", crate::feed::YOUTUBE_URL);
+        let html = reqwest::get(url).await.unwrap().text().await.unwrap();
         assert_eq!(
-            get_channel_id("https://www.youtube.com/c/GrimBeard")
-                .await
-                .unwrap(),
-            grim_beard
+            get_channel_id(&Html::parse_document(&html)).unwrap(),
+            "UCNmv1Cmjm3Hk8Vc9kIgv0AQ"
         );
     }
-    #[tokio::test]
-    async fn test_vihart_id() {
-        let vihart = "UCOGeU-1Fig3rrDjhm9Zs_wg";
+
+    #[test]
+    fn test_feed_page() {
         assert_eq!(
-            get_channel_id("https://www.youtube.com/user/vihart")
-                .await
-                .unwrap(),
-            vihart
+            FeedPage::parse(&fixture("@fixture")).unwrap(),
+            FeedPage {
+                channel_id: Some("UCfixture0000000000000000".to_string()),
+                image: "https://yt3.googleusercontent.com/fixture=s900".to_string(),
+                description: "A channel recorded for offline tests.".to_string(),
+            }
         );
     }
 }
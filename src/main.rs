@@ -2,16 +2,23 @@ use axum::{routing::get, Router};
 use std::io::IsTerminal;
 use std::net::SocketAddr;
 use std::process::ExitCode;
+use std::time::Duration;
 use tower_http::trace::TraceLayer;
 
 mod audio;
 mod cli;
+mod config;
 mod error;
 mod feed;
+mod sponsorblock;
+mod state;
 mod trace_layer;
+mod upstream;
 
 use crate::cli::Cli;
+use crate::config::Config;
 use crate::error::Result;
+use crate::state::AppState;
 use clap::Parser;
 
 #[tokio::main]
@@ -32,11 +39,15 @@ async fn main() -> Result<ExitCode> {
         .on_request(trace_layer::trace_layer_on_request)
         .on_response(trace_layer::trace_layer_on_response);
 
-    let app = Router::new()
-        .route("/:path_type", get(feed::serve_feed))
-        .route("/:path_type/*val", get(feed::serve_feed))
-        .route("/ep/:feed_id/:file_name", get(audio::return_audio))
-        .layer(trace_layer);
+    let config = Config::load(cli.config.as_deref())?;
+    let state = AppState::new(&cli, config)?;
+    state
+        .cache
+        .clone()
+        .spawn_sweeper(Duration::from_secs(cli.cache_sweep_interval));
+    feed::spawn_refresher(state.clone());
+
+    let app = router(state).layer(trace_layer);
 
     tracing::info!("Listening on {}:{}", cli.host, cli.port);
     let addr = SocketAddr::new(cli.host, cli.port);
@@ -49,3 +60,14 @@ async fn main() -> Result<ExitCode> {
 
     Ok(ExitCode::SUCCESS)
 }
+
+pub(crate) fn router(state: AppState) -> Router {
+    Router::new()
+        .route("/:path_type", get(feed::serve_feed))
+        .route("/:path_type/*val", get(feed::serve_feed))
+        .route(
+            "/ep/:feed_id/:file_name",
+            get(audio::return_audio).head(audio::head_audio),
+        )
+        .with_state(state)
+}
@@ -0,0 +1,115 @@
+use serde::Deserialize;
+
+use crate::audio::SponsorCategory;
+use crate::error::Result;
+use crate::upstream::Upstream;
+
+const SKIP_SEGMENTS_URL: &str = "https://sponsor.ajay.app/api/skipSegments";
+
+/// A crowd-sourced SponsorBlock segment for one video.
+#[derive(Debug, Clone, Deserialize)]
+pub(crate) struct Segment {
+    /// Start and end of the segment, in seconds.
+    pub(crate) segment: (f64, f64),
+    #[serde(rename = "UUID")]
+    pub(crate) uuid: String,
+    #[serde(rename = "actionType")]
+    pub(crate) action_type: String,
+    pub(crate) category: SponsorCategory,
+}
+
+/// Fetch the segments of `categories` for `video_id` that yt-dlp would cut.
+#[tracing::instrument(skip(api))]
+pub(crate) async fn segments(
+    api: &Upstream,
+    video_id: &str,
+    categories: &[SponsorCategory],
+) -> Result<Vec<Segment>> {
+    let categories = categories
+        .iter()
+        .map(|category| format!(r#""{}""#, category.as_str()))
+        .collect::<Vec<_>>()
+        .join(",");
+
+    let resp = api
+        .get(SKIP_SEGMENTS_URL)
+        .await
+        .query(&[
+            ("videoID", video_id),
+            ("categories", &format!("[{categories}]")),
+        ])
+        .send()
+        .await?;
+
+    // SponsorBlock answers 404 when a video has no segments yet.
+    if resp.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(vec![]);
+    }
+
+    let segments: Vec<Segment> = resp.error_for_status()?.json().await?;
+    Ok(segments
+        .into_iter()
+        .filter(|segment| segment.action_type == "skip")
+        .collect())
+}
+
+/// The sorted segment UUIDs, which identify a segment set.
+pub(crate) fn uuids(segments: &[Segment]) -> Vec<String> {
+    let mut uuids: Vec<String> = segments.iter().map(|s| s.uuid.clone()).collect();
+    uuids.sort();
+    uuids
+}
+
+/// Total seconds covered by `segments`, counting overlapping segments once.
+pub(crate) fn removed_secs(segments: &[Segment]) -> u32 {
+    let removed: f64 = merged(segments)
+        .iter()
+        .map(|(start, end)| end - start)
+        .sum();
+    removed.round() as u32
+}
+
+/// The spans `segments` cover, sorted, with overlapping ones merged.
+pub(crate) fn merged(segments: &[Segment]) -> Vec<(f64, f64)> {
+    let mut spans: Vec<(f64, f64)> = segments.iter().map(|s| s.segment).collect();
+    spans.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut merged: Vec<(f64, f64)> = vec![];
+    for (start, end) in spans {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+/// Where `time` in the original video ends up once the `removed` spans, as
+/// returned by [`merged`], are cut out of it.
+pub(crate) fn cut_time(removed: &[(f64, f64)], time: f64) -> f64 {
+    let before: f64 = removed
+        .iter()
+        .map(|(start, end)| (end.min(time) - start).max(0.0))
+        .sum();
+    time - before
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn skip(start: f64, end: f64) -> Segment {
+        Segment {
+            segment: (start, end),
+            uuid: format!("{start}-{end}"),
+            action_type: "skip".to_string(),
+            category: SponsorCategory::Sponsor,
+        }
+    }
+
+    #[test]
+    fn test_overlapping_segments_count_once() {
+        let segments = [skip(100.0, 160.0), skip(10.0, 40.0), skip(30.0, 70.5)];
+        assert_eq!(removed_secs(&segments), 121);
+    }
+}
@@ -0,0 +1,123 @@
+use std::{path::PathBuf, sync::Arc, time::Duration};
+
+use url::Url;
+
+use crate::audio::{Cache, DownloadPool, Downloader, Downloads};
+use crate::cli::Cli;
+use crate::config::Config;
+use crate::error::Result;
+use crate::feed::{MetadataSource, Refreshing, Store, YouTube};
+use crate::upstream::Upstream;
+
+/// How long connecting to YouTube or SponsorBlock may take, out of the
+/// whole request's timeout.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Name of the database in the data directory, unless it is put elsewhere.
+const DATABASE: &str = "vpod.db";
+
+/// Shared state handed to every axum handler.
+#[derive(Clone)]
+pub(crate) struct AppState {
+    pub(crate) config: Arc<Config>,
+    pub(crate) downloads: Downloads,
+    pub(crate) pool: DownloadPool,
+    pub(crate) cache: Cache,
+    pub(crate) downloader: Arc<dyn Downloader>,
+    pub(crate) source: Arc<dyn MetadataSource>,
+    /// SponsorBlock's API. It and YouTube share one HTTP connection pool.
+    pub(crate) sponsorblock_api: Upstream,
+    pub(crate) store: Store,
+    pub(crate) refreshing: Refreshing,
+    /// How often each feed is refreshed in the background.
+    pub(crate) refresh_interval: Duration,
+    /// How long clients may cache a feed response.
+    pub(crate) feed_max_age: Duration,
+    pub(crate) episode_url: Url,
+    pub(crate) data_dir: PathBuf,
+}
+
+impl AppState {
+    pub(crate) fn new(cli: &Cli, config: Config) -> Result<Self> {
+        let downloader = cli.downloader.build();
+        let http = http_client(cli);
+        let database = match &cli.database {
+            Some(database) => database.clone(),
+            None => cli.data_dir.join(DATABASE),
+        };
+        let store = Store::open(&database, &cli.data_dir)?;
+        store.replace_feed_settings(config.feeds())?;
+        let pool = DownloadPool::new(cli.download_workers, cli.download_queue);
+        Ok(Self {
+            config: Arc::new(config),
+            downloads: Downloads::default(),
+            pool: pool.clone(),
+            cache: Cache::new(cli, store.clone()),
+            source: Arc::new(YouTube::new(
+                cli.youtube_url.clone(),
+                Upstream::new(http.clone(), cli.youtube_rate_limit),
+                downloader.clone(),
+                pool,
+                cli.data_dir.clone(),
+            )),
+            downloader,
+            sponsorblock_api: Upstream::new(http, cli.sponsorblock_rate_limit),
+            store,
+            refreshing: Refreshing::default(),
+            refresh_interval: Duration::from_secs(cli.refresh_interval),
+            feed_max_age: Duration::from_secs(cli.feed_max_age),
+            episode_url: cli.episode_url.clone(),
+            data_dir: cli.data_dir.clone(),
+        })
+    }
+
+    /// Directory the feed `feed_id` and its episodes are stored in.
+    pub(crate) fn feed_dir(&self, feed_id: &str) -> PathBuf {
+        self.data_dir.join(feed_id)
+    }
+}
+
+fn http_client(cli: &Cli) -> reqwest::Client {
+    let timeout = Duration::from_secs(cli.http_timeout);
+    reqwest::Client::builder()
+        .timeout(timeout)
+        .connect_timeout(timeout.min(CONNECT_TIMEOUT))
+        .build()
+        .expect("could not build HTTP client")
+}
+
+/// State over a fresh data directory named after `test`, downloading with
+/// the fake downloader and building feeds from recorded fixtures. `args` are
+/// added to the command line.
+#[cfg(test)]
+pub(crate) async fn test_state(test: &str, config: &str, args: &[&str]) -> (AppState, PathBuf) {
+    use clap::Parser;
+
+    let youtube_url = crate::feed::serve_fixtures().await;
+    let data_dir = std::env::temp_dir().join(format!("vpod-{test}-test-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&data_dir);
+    std::fs::create_dir_all(&data_dir).unwrap();
+
+    let cli = Cli::parse_from(
+        [
+            "vpod",
+            "--episode-url",
+            "http://localhost/",
+            "--data-dir",
+            data_dir.to_str().unwrap(),
+            "--downloader",
+            "fake",
+            "--youtube-url",
+            youtube_url.as_str(),
+            "--youtube-rate-limit",
+            "0",
+        ]
+        .iter()
+        .chain(args),
+    );
+
+    (
+        AppState::new(&cli, Config::parse(config).unwrap()).unwrap(),
+        data_dir,
+    )
+}
@@ -0,0 +1,101 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::time::Instant;
+
+/// A service we fetch from, e.g. YouTube or SponsorBlock, reached through
+/// the shared HTTP client no faster than its rate limit allows.
+#[derive(Clone)]
+pub(crate) struct Upstream {
+    client: reqwest::Client,
+    limit: Arc<RateLimit>,
+}
+
+impl Upstream {
+    /// At most `per_sec` requests a second, or as many as asked for if it
+    /// isn't positive.
+    pub(crate) fn new(client: reqwest::Client, per_sec: f64) -> Self {
+        Self {
+            client,
+            limit: Arc::new(RateLimit::new(per_sec)),
+        }
+    }
+
+    /// Wait for this upstream's turn, then start a GET request.
+    pub(crate) async fn get(&self, url: impl reqwest::IntoUrl) -> reqwest::RequestBuilder {
+        self.limit.wait().await;
+        self.client.get(url)
+    }
+
+    /// Wait for this upstream's turn for a request made some other way,
+    /// e.g. by yt-dlp.
+    pub(crate) async fn wait(&self) {
+        self.limit.wait().await;
+    }
+}
+
+/// Spaces requests evenly, handing out one slot every `period`.
+struct RateLimit {
+    period: Duration,
+    next: Mutex<Instant>,
+}
+
+impl RateLimit {
+    fn new(per_sec: f64) -> Self {
+        let period = match per_sec > 0.0 {
+            true => Duration::from_secs_f64(1.0 / per_sec),
+            false => Duration::ZERO,
+        };
+        Self {
+            period,
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Wait for the next free slot.
+    async fn wait(&self) {
+        let slot = {
+            let mut next = self.next.lock().unwrap_or_else(|e| e.into_inner());
+            let slot = (*next).max(Instant::now());
+            *next = slot + self.period;
+            slot
+        };
+        if slot > Instant::now() {
+            tokio::time::sleep_until(slot).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_requests_are_spaced_out() {
+        let limit = RateLimit::new(20.0);
+        let start = Instant::now();
+
+        // The first goes right away, the other four a twentieth apart.
+        for _ in 0..5 {
+            limit.wait().await;
+        }
+
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(200), "{elapsed:?}");
+        assert!(elapsed < Duration::from_secs(1), "{elapsed:?}");
+    }
+
+    #[tokio::test]
+    async fn test_unlimited() {
+        let limit = RateLimit::new(0.0);
+        let start = Instant::now();
+
+        for _ in 0..100 {
+            limit.wait().await;
+        }
+
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+}